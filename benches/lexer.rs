@@ -0,0 +1,42 @@
+// Lexer throughput on a large generated source full of long identifiers and strings.
+// Run with `cargo bench --bench lexer`.
+use std::time::Instant;
+
+#[path = "../src/lexing/mod.rs"]
+#[allow(dead_code)]
+mod lexing;
+
+use lexing::lexer::Lexer;
+
+const STATEMENTS: usize = 20_000;
+const RUNS: usize = 5;
+
+fn generate_source() -> String {
+    let identifier = "a_rather_long_identifier_name_".repeat(8);
+    let text = "some fairly long string contents ".repeat(16);
+    let mut source = String::from("procedure main {\n");
+    for i in 0..STATEMENTS {
+        source.push_str(&format!("  define {}{} := \"{}\";\n", identifier, i, text));
+        source.push_str(&format!("  print(\"{{}}\", {}{} + 1_000.5);\n", identifier, i));
+    }
+    source.push_str("}\n");
+    source
+}
+
+fn main() {
+    let source = generate_source();
+    let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+
+    let mut best = f64::MAX;
+    let mut token_count = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        token_count = Lexer::new(source.chars().peekable()).count();
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+
+    println!(
+        "lexed {:.1} MiB into {} tokens in {:.3}s ({:.1} MiB/s, best of {})",
+        megabytes, token_count, best, megabytes / best, RUNS
+    );
+}
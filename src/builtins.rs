@@ -0,0 +1,69 @@
+use inkwell::values::BasicValueEnum;
+
+use crate::codegen::ir::CodeGenerator;
+use crate::parsing::node::{ProcedureCallNode, TypeNode};
+use crate::parsing::semantic_analyzer::SemanticAnalyzer;
+
+pub type AnalyzeHook = fn(&mut SemanticAnalyzer, &mut ProcedureCallNode, usize);
+pub type GenerateHook = for<'ctx> fn(&ProcedureCallNode, &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>>;
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub param_types: &'static [TypeNode],
+    pub return_type: TypeNode,
+    pub variadic: bool,
+    /// Replaces the regular argument count and type checks when the builtin needs its own.
+    pub analyze: Option<AnalyzeHook>,
+    pub generate: GenerateHook,
+}
+
+impl Builtin {
+    pub fn signature(&self) -> String {
+        let mut params: Vec<String> = self.param_types.iter().map(TypeNode::to_string).collect();
+        if self.variadic {
+            params.push("...".to_string());
+        }
+        format!("{}({}) -> {}", self.name, params.join(", "), self.return_type)
+    }
+}
+
+// Procedures every program can call without declaring them. The analyzer registers them in the
+// root scope and the code generator dispatches calls to them through `generate`.
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "print",
+        param_types: &[TypeNode::StringType],
+        return_type: TypeNode::VoidType,
+        variadic: true,
+        analyze: Some(SemanticAnalyzer::analyze_print_call),
+        generate: ProcedureCallNode::generate_print_code,
+    },
+    Builtin {
+        name: "format",
+        param_types: &[TypeNode::StringType],
+        return_type: TypeNode::StringType,
+        variadic: true,
+        analyze: Some(SemanticAnalyzer::analyze_format_call),
+        generate: ProcedureCallNode::generate_format_code,
+    },
+    Builtin {
+        name: "read_or",
+        param_types: &[TypeNode::NumberType],
+        return_type: TypeNode::NumberType,
+        variadic: false,
+        analyze: None,
+        generate: ProcedureCallNode::generate_read_or_code,
+    },
+    Builtin {
+        name: "flush",
+        param_types: &[],
+        return_type: TypeNode::VoidType,
+        variadic: false,
+        analyze: None,
+        generate: ProcedureCallNode::generate_flush_code,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
@@ -1,19 +1,66 @@
 use core::fmt;
 use std::{
     error::Error,
+    ffi::{c_char, CString},
     fs::File,
-    io::{Read, Write},
-    path::Path
+    io::{self, Read, Write},
+    path::Path,
+    ptr,
 };
 
-use inkwell::context::Context;
+use inkwell::{
+    context::Context,
+    execution_engine::ExecutionEngine,
+    targets::{InitializationConfig, Target},
+    OptimizationLevel,
+};
 
 use crate::{
-    codegen::{elf::MachineCodeGenerator, ir::CodeGenerator},
+    builtins::BUILTINS,
+    codegen::{elf::MachineCodeGenerator, ir::{target_machine, CodeGenerator}},
     lexing::{lexer::Lexer, token::{Token, TokenType}},
-    parsing::{node::ProgramNode, parser::Parser, semantic_analyzer::SemanticAnalyzer},
+    parsing::{diagnostic::DiagnosticKind, node::{ProgramNode, StatementNode}, parser::Parser, semantic_analyzer::{SemanticAnalyzer, ShadowingPolicy}},
 };
 
+/// The last artifact the pipeline produces before stopping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    LlvmIr,
+    Asm,
+    Obj,
+    Exe,
+}
+
+/// How the IR is turned into assembly and object code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// Shell out to clang for assembly, object code and linking
+    Clang,
+    /// Write assembly and object code through LLVM directly, only linking externally
+    Llvm,
+}
+
+/// Which snapshots of the IR to keep around the optimization passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpIr {
+    /// `<name>.unopt.ll`, before the passes run
+    Unopt,
+    /// `<name>.opt.ll`, after the passes run
+    Opt,
+    Both,
+}
+
+/// How diagnostics from semantic analysis are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Rendered with the offending source line, on stderr
+    Human,
+    /// One JSON object per diagnostic followed by a summary with the counts, on stdout
+    Json,
+}
+
 pub struct Config<'a> {
     pub input_file: &'a Path,
     pub tokens_file: Option<&'a str>,
@@ -21,16 +68,49 @@ pub struct Config<'a> {
     pub symbol_table_file: Option<&'a str>,
     pub ir_file: &'a str,
     pub exe_file: &'a str,
+    pub profile: bool,
+    pub sanitize: Option<&'a str>,
+    pub symbol_tree: bool,
+    pub pretty: bool,
+    pub emit: Emit,
+    pub jit: bool,
+    pub opt_level: u8,
+    pub backend: Backend,
+    pub target: Option<&'a str>,
+    pub dump_ir: Option<DumpIr>,
+    pub auto_flush: bool,
+    pub implicit_main: bool,
+    pub max_loop_iterations: Option<u64>,
+    pub check: bool,
+    pub message_format: MessageFormat,
+    pub entry: &'a str,
+    pub module_name: &'a str,
+    pub ir_to_stdout: bool,
+    pub verbose: bool,
+    pub shadowing: ShadowingPolicy,
+    pub max_params: usize,
+    pub werror_unused: bool,
 }
 
+// A path of `-` reads the source from stdin instead of a file.
 pub fn read_input_file(path: &Path) -> Result<String, Box<dyn Error>> {
-    let mut file = File::open(path)?;
-    let mut input = String::new();
-    file.read_to_string(&mut input)?;
-    Ok(input)
+    let mut bytes = Vec::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        File::open(path)?.read_to_end(&mut bytes)?;
+    }
+    let input = String::from_utf8(bytes)
+        .map_err(|e| format!("{} is not valid UTF-8: {}", path.display(), e.utf8_error()))?;
+
+    // Editors on Windows like to start files with a byte order mark, which isn't part of the source.
+    match input.strip_prefix('\u{feff}') {
+        Some(stripped) => Ok(stripped.to_string()),
+        None => Ok(input),
+    }
 }
 
-pub fn process_tokens(config: &Config, input: String) -> Result<Vec<Token>, Box<dyn Error>> {
+pub fn process_tokens(config: &Config, input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
     let lexer = Lexer::new(input.chars().peekable());
     let tokens: Vec<Token> = lexer.collect();
 
@@ -41,7 +121,7 @@ pub fn process_tokens(config: &Config, input: String) -> Result<Vec<Token>, Box<
                 "Invalid token at line {}, column {}: {}",
                 token.line,
                 token.column,
-                token.lexeme
+                token.invalid_reason().unwrap_or(token.lexeme.clone())
             );
             has_invalid_tokens = true;
         }
@@ -56,8 +136,13 @@ pub fn process_tokens(config: &Config, input: String) -> Result<Vec<Token>, Box<
         let mut file = File::create(tokens_file)?;
         println!("================================================");
         println!("Writing tokens to file");
+        // JSON users get every field of the token, everyone else one readable line per token.
         for token in &tokens {
-            writeln!(file, "{:?}", token)?;
+            if config.message_format == MessageFormat::Json {
+                writeln!(file, "{:?}", token)?;
+            } else {
+                writeln!(file, "{}", token)?;
+            }
         }
         println!("Tokens written to file: {}", tokens_file);
     }
@@ -65,8 +150,17 @@ pub fn process_tokens(config: &Config, input: String) -> Result<Vec<Token>, Box<
     Ok(tokens)
 }
 
-pub fn parse_ast(tokens: Vec<Token>) -> Result<ProgramNode, Box<dyn Error>> {
+pub fn parse_ast(config: &Config, tokens: Vec<Token>) -> Result<ProgramNode, Box<dyn Error>> {
+    // A file with nothing but whitespace and comments would otherwise only report the missing main procedure.
+    if tokens.is_empty() {
+        println!("================================================");
+        println!("Parsing errors:\nSource file is empty");
+        return Err(Box::new(fmt::Error));
+    }
+
     let mut parser = Parser::new(tokens);
+    parser.implicit_main = config.implicit_main;
+    parser.entry = config.entry.to_string();
     let ast = match parser.parse() {
         Ok(val) => val,
         Err(e) => {
@@ -78,36 +172,157 @@ pub fn parse_ast(tokens: Vec<Token>) -> Result<ProgramNode, Box<dyn Error>> {
     Ok(ast)
 }
 
-pub fn analyze_ast(ast: &mut ProgramNode) -> Result<SemanticAnalyzer, Box<dyn Error>> {
+pub fn analyze_ast(ast: &mut ProgramNode, source: &str, config: &Config) -> Result<SemanticAnalyzer, Box<dyn Error>> {
+    let message_format = config.message_format;
     let mut analyzer = SemanticAnalyzer::new();
-    match analyzer.analyze(ast) {
-        Ok(val) => val,
-        Err(e) => {
-            eprintln!("================================================");
-            eprintln!("Semantic Analysis errors:\n{}", e);
-            return Err(Box::new(fmt::Error));
+    analyzer.shadowing = config.shadowing;
+    analyzer.max_params = config.max_params;
+    let mut result = analyzer.analyze(ast);
+    if config.werror_unused {
+        // Only unused-symbol warnings become errors, every other warning stays a warning.
+        let (unused, warnings): (Vec<_>, Vec<_>) = analyzer.warnings.drain(..).partition(|warning| warning.kind == DiagnosticKind::Unused);
+        analyzer.warnings = warnings;
+        if !unused.is_empty() {
+            result = Err(result.err().unwrap_or_default().into_iter().chain(unused).collect());
+        }
+    }
+    if message_format == MessageFormat::Json {
+        let errors = result.as_ref().err().map_or(&[][..], Vec::as_slice);
+        for error in errors {
+            println!("{}", error.to_json("error"));
         }
-    };
-    if !analyzer.warnings.is_empty() {
-        eprintln!("================================================");
-        eprintln!("Semantic Analysis warnings:\n");
         for warning in &analyzer.warnings {
-            eprintln!("Warning: {}", warning);
+            println!("{}", warning.to_json("warning"));
+        }
+        println!("{{\"kind\":\"summary\",\"errors\":{},\"warnings\":{}}}", errors.len(), analyzer.warnings.len());
+        return match result {
+            Ok(()) => Ok(analyzer),
+            Err(_) => Err(Box::new(fmt::Error)),
+        };
+    }
+    // One `file:line:column: kind: message` line per diagnostic, errors followed by their source
+    // excerpt. The section banners are only shown with `--verbose`.
+    let file_name = if config.input_file == Path::new("-") { "<stdin>".to_string() } else { config.input_file.display().to_string() };
+    if let Err(errors) = result {
+        if config.verbose {
+            eprintln!("================================================");
+            eprintln!("Semantic Analysis errors:");
         }
+        for error in &errors {
+            eprintln!("{}", error.to_line(&file_name, "error"));
+            if let Some(excerpt) = error.excerpt(source) {
+                eprintln!("{}", excerpt);
+            }
+        }
+        return Err(Box::new(fmt::Error));
+    }
+    if !analyzer.warnings.is_empty() && config.verbose {
+        eprintln!("================================================");
+        eprintln!("Semantic Analysis warnings:");
+    }
+    for warning in &analyzer.warnings {
+        eprintln!("{}", warning.to_line(&file_name, "warning"));
     }
     Ok(analyzer)
 }
 
-pub fn generate_ir(ast: &ProgramNode, filename: &str) -> Result<(), Box<dyn Error>> {
+pub fn generate_ir(ast: &ProgramNode, config: &Config) -> Result<(), Box<dyn Error>> {
+    let filename = config.ir_file;
     let llvmcontext = Context::create();
-    let mut ir_generator = CodeGenerator::new("ziget", &llvmcontext);
+    let mut ir_generator = CodeGenerator::new(config.module_name, &llvmcontext);
+    ir_generator.profile = config.profile;
+    ir_generator.auto_flush = config.auto_flush;
+    ir_generator.max_loop_iterations = config.max_loop_iterations;
+    if let Some(triple) = config.target {
+        ir_generator.set_target(triple)?;
+    }
 
     ir_generator.generate_code(ast);
+    ir_generator.module.verify().map_err(|e| e.to_string())?;
+
+    let ir_base_name = config.ir_file.trim_end_matches(".ll");
+    if matches!(config.dump_ir, Some(DumpIr::Unopt | DumpIr::Both)) {
+        let unopt_file = format!("{}.unopt.ll", ir_base_name);
+        ir_generator.write_to_file(&unopt_file);
+        println!("Unoptimized IR written to file: {}", unopt_file);
+    }
+    ir_generator.optimize(config.opt_level)?;
+    if matches!(config.dump_ir, Some(DumpIr::Opt | DumpIr::Both)) {
+        let opt_file = format!("{}.opt.ll", ir_base_name);
+        ir_generator.write_to_file(&opt_file);
+        println!("Optimized IR written to file: {}", opt_file);
+    }
 
     println!("================================================");
     println!("Writing IR to file");
     ir_generator.write_to_file(filename);
     println!("IR written to file: {}", filename);
+    if config.ir_to_stdout {
+        print!("{}", ir_generator.module.print_to_string().to_string());
+    }
+
+    if config.backend == Backend::Llvm && config.emit != Emit::LlvmIr {
+        let level = match config.opt_level {
+            0 => OptimizationLevel::None,
+            1 => OptimizationLevel::Less,
+            2 => OptimizationLevel::Default,
+            _ => OptimizationLevel::Aggressive,
+        };
+        let machine = target_machine(config.target, level)?;
+        if config.emit == Emit::Asm {
+            ir_generator.write_assembly_file(&asm_file_name(config), &machine)?;
+        } else {
+            ir_generator.write_object_file(&obj_file_name(config), &machine)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Generates the program in memory and executes `main` through LLVM's JIT,
+// so nothing is written to disk and clang isn't needed.
+pub fn run_jit(ast: &ProgramNode, config: &Config) -> Result<(), Box<dyn Error>> {
+    Target::initialize_native(&InitializationConfig::default())?;
+
+    let llvmcontext = Context::create();
+    let mut ir_generator = CodeGenerator::new(config.module_name, &llvmcontext);
+    ir_generator.profile = config.profile;
+    ir_generator.auto_flush = config.auto_flush;
+    ir_generator.max_loop_iterations = config.max_loop_iterations;
+
+    ir_generator.generate_code(ast);
+    ir_generator.module.verify().map_err(|e| e.to_string())?;
+    ir_generator.optimize(config.opt_level)?;
+    if config.ir_to_stdout {
+        print!("{}", ir_generator.module.print_to_string().to_string());
+    }
+
+    let engine = ir_generator.module
+        .create_jit_execution_engine(OptimizationLevel::Default)
+        .map_err(|e| e.to_string())?;
+
+    unsafe {
+        if ast.main.params.is_empty() {
+            let main = engine
+                .get_function::<unsafe extern "C" fn()>(&ast.main.name)
+                .map_err(|e| e.to_string())?;
+            main.call();
+        } else {
+            // Programs run through the JIT get no arguments besides their own name.
+            let main = engine
+                .get_function::<unsafe extern "C" fn(i32, *const *const c_char)>(&ast.main.name)
+                .map_err(|e| e.to_string())?;
+            let program_name = CString::new(config.input_file.to_string_lossy().into_owned())?;
+            let argv = [program_name.as_ptr(), ptr::null()];
+            main.call(1, argv.as_ptr());
+        }
+    }
+
+    // The profile dump is registered with atexit and lives in JIT-compiled code,
+    // so the engine has to outlive this function for it to still be callable.
+    if config.profile {
+        std::mem::forget(engine);
+    }
 
     Ok(())
 }
@@ -117,7 +332,13 @@ pub fn write_parse_tree(ast: &ProgramNode, config: &Config) -> Result<(), Box<dy
         println!("================================================");
         println!("Writing Parse Tree to file");
         let mut file = File::create(tree_file)?;
-        writeln!(file, "{:#?}", ast)?;
+        if config.pretty {
+            let mut tree = String::new();
+            ast.render_tree(&mut tree);
+            write!(file, "{}", tree)?;
+        } else {
+            writeln!(file, "{:#?}", ast)?;
+        }
         println!("Parse Tree written to file: {}", tree_file);
     }
     Ok(())
@@ -126,48 +347,233 @@ pub fn write_symbol_table(analyzer: &SemanticAnalyzer, config: &Config) -> Resul
     if let Some(symbol_table_file) = &config.symbol_table_file {
         println!("Writing symbol tables to file...");
         let mut file = File::create(symbol_table_file)?;
-        for table in &analyzer.symbol_tables {
-            writeln!(file, "{:#?}", table)?;
+        if config.symbol_tree {
+            if let Some(root) = analyzer.symbol_tables.get(&0) {
+                let mut tree = String::new();
+                root.render_tree(&analyzer.symbol_tables, 0, &mut tree);
+                write!(file, "{}", tree)?;
+            }
+        } else {
+            let mut ids: Vec<&usize> = analyzer.symbol_tables.keys().collect();
+            ids.sort();
+            for id in ids {
+                writeln!(file, "{:#?}", analyzer.symbol_tables[id])?;
+            }
         }
         println!("Symbol tables written to file: {}", symbol_table_file);
     }
     Ok(())
 }
 
+fn obj_file_name(config: &Config) -> String {
+    format!("{}.o", &config.ir_file.trim_end_matches(".ll"))
+}
+
+fn asm_file_name(config: &Config) -> String {
+    format!("{}.s", &config.ir_file.trim_end_matches(".ll"))
+}
+
+// Shows clang's own diagnostics as they are instead of as an escaped error string.
+fn report_clang_error(error: String) -> Box<dyn Error> {
+    eprintln!("================================================");
+    eprintln!("Machine code generation errors:\n{}", error);
+    Box::new(fmt::Error)
+}
+
 pub fn compile_and_link(config: &Config) -> Result<(), Box<dyn Error>> {
-    let obj_filename = format!("{}.o", &config.ir_file.trim_end_matches(".ll"));
-    let asm_filename = format!("{}.s", &config.ir_file.trim_end_matches(".ll"));
-    let elf_generator = MachineCodeGenerator::new();
-    elf_generator.generate_assembly_file(&config.ir_file, &asm_filename);
-    elf_generator.generate_object_file(&asm_filename, &obj_filename);
-    elf_generator.link_executable(&obj_filename, &config.exe_file);
+    let obj_filename = obj_file_name(config);
+    let asm_filename = asm_file_name(config);
+
+    // The LLVM backend already wrote the assembly or object file while generating the IR.
+    if config.backend == Backend::Llvm {
+        match config.emit {
+            Emit::Asm => println!("Assembly written to file: {}", asm_filename),
+            Emit::Obj => println!("Object code written to file: {}", obj_filename),
+            _ => {
+                let mut linker = MachineCodeGenerator::linker_only();
+                linker.target = config.target.map(str::to_string);
+                linker.verbose = config.verbose;
+                linker.link_executable(&obj_filename, &config.exe_file).map_err(report_clang_error)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut elf_generator = MachineCodeGenerator::new();
+    elf_generator.sanitize = config.sanitize.map(str::to_string);
+    elf_generator.target = config.target.map(str::to_string);
+    elf_generator.verbose = config.verbose;
+    elf_generator.check_sanitizer_support()?;
+    elf_generator.generate_assembly_file(&config.ir_file, &asm_filename).map_err(report_clang_error)?;
+    if config.emit == Emit::Asm {
+        println!("Assembly written to file: {}", asm_filename);
+        return Ok(());
+    }
+    elf_generator.generate_object_file(&asm_filename, &obj_filename).map_err(report_clang_error)?;
+    if config.emit == Emit::Obj {
+        println!("Object code written to file: {}", obj_filename);
+        return Ok(());
+    }
+    elf_generator.link_executable(&obj_filename, &config.exe_file).map_err(report_clang_error)?;
     Ok(())
 }
 
+// Status messages would end up mixed into the diagnostics on stdout with `--message-format json`.
+fn progress(config: &Config, message: &str) {
+    if config.message_format == MessageFormat::Human {
+        println!("{}", message);
+    }
+}
+
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    println!("Reading input file...");
+    progress(&config, "Reading input file...");
     let input = read_input_file(config.input_file)?;
 
-    println!("Lexing input...");
-    let tokens = process_tokens(&config, input)?;
+    progress(&config, "Lexing input...");
+    let tokens = process_tokens(&config, &input)?;
+    if config.emit == Emit::Tokens {
+        return Ok(());
+    }
 
-    println!("Parsing tokens...");
-    let mut ast = parse_ast(tokens)?;
+    progress(&config, "Parsing tokens...");
+    let mut ast = parse_ast(&config, tokens)?;
 
-    println!("Analyzing parse tree...");
-    let analyzer = analyze_ast(&mut ast)?;
+    progress(&config, "Analyzing parse tree...");
+    let analyzer = analyze_ast(&mut ast, &input, &config)?;
 
 
     write_parse_tree(&ast, &config)?;
     write_symbol_table(&analyzer, &config)?;
+    if config.emit == Emit::Ast {
+        return Ok(());
+    }
+    if config.check {
+        progress(&config, &format!("No errors found in {}", config.input_file.display()));
+        return Ok(());
+    }
 
+    if config.jit {
+        progress(&config, "Running program...");
+        return run_jit(&ast, &config);
+    }
 
-    println!("Generating intermediate code...");
-    generate_ir(&ast, &config.ir_file)?;
 
-    println!("Generating machine code...");
+    progress(&config, "Generating intermediate code...");
+    generate_ir(&ast, &config)?;
+    if config.emit == Emit::LlvmIr {
+        return Ok(());
+    }
+
+    progress(&config, "Generating machine code...");
     compile_and_link(&config)?;
-    println!("Compiled successfully to {}!", &config.exe_file);
+    if config.emit == Emit::Exe {
+        progress(&config, &format!("Compiled successfully to {}!", &config.exe_file));
+    }
 
     Ok(())
 }
+
+pub fn list_builtins() {
+    for builtin in BUILTINS {
+        println!("{}", builtin.signature());
+    }
+}
+
+// The expression is always run through the JIT from the main wrapped around it, every other
+// setting comes from the flags like it would for a file.
+pub fn eval(expression: &str, config: Config) -> Result<(), Box<dyn Error>> {
+    let source = format!("procedure main() {{ print(\"{{}}\", {}); }}", expression);
+    let config = Config { entry: "main", ..config };
+
+    let tokens = process_tokens(&config, &source)?;
+    let mut ast = match parse_ast(&config, tokens) {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprintln!("Could not parse expression: {}", expression);
+            return Err(e);
+        }
+    };
+    analyze_ast(&mut ast, &source, &config)?;
+
+    run_jit(&ast, &config)
+}
+
+// Reads statements from stdin one line at a time and runs each as soon as it is entered, printing
+// the value of bare expressions. Every line is compiled into a module of its own and added to the
+// same JIT, with the variables declared so far kept in globals so later lines can use them.
+pub fn repl() -> Result<(), Box<dyn Error>> {
+    Target::initialize_native(&InitializationConfig::default())?;
+
+    let llvmcontext = Context::create();
+    let mut analyzer = SemanticAnalyzer::new();
+    let session_table_id = analyzer.begin_session();
+    let mut session_variables = Vec::new();
+    let mut engine: Option<ExecutionEngine> = None;
+    let mut line_number = 0;
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        let mut source = line.trim_end().to_string();
+        if source.is_empty() {
+            continue;
+        }
+        // The semicolon after a single statement is optional here.
+        if !source.ends_with(';') && !source.ends_with('}') {
+            source.push(';');
+        }
+
+        let tokens: Vec<Token> = Lexer::new(source.chars().peekable()).collect();
+        if let Some(token) = tokens.iter().find(|token| token.token_type == TokenType::Invalid) {
+            eprintln!("Invalid token at column {}: {}", token.column, token.invalid_reason().unwrap_or(token.lexeme.clone()));
+            continue;
+        }
+        let mut statement = match Parser::new(tokens).parse_repl_line() {
+            Ok(statement) => statement,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        if let Err(errors) = analyzer.analyze_session_statement(&mut statement, session_table_id) {
+            for error in &errors {
+                eprintln!("{}", error.render(&source));
+            }
+            continue;
+        }
+
+        line_number += 1;
+        let function_name = format!("repl.line{}", line_number);
+        let mut ir_generator = CodeGenerator::new(&format!("repl{}", line_number), &llvmcontext);
+        ir_generator.auto_flush = true;
+        ir_generator.generate_repl_line(&function_name, &statement, &session_variables);
+        ir_generator.module.verify().map_err(|e| e.to_string())?;
+
+        if let Some(engine) = &engine {
+            engine.add_module(&ir_generator.module).map_err(|_| "Could not add the line to the JIT".to_string())?;
+        } else {
+            engine = Some(
+                ir_generator.module
+                    .create_jit_execution_engine(OptimizationLevel::Default)
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        unsafe {
+            let line_function = engine
+                .as_ref()
+                .unwrap()
+                .get_function::<unsafe extern "C" fn()>(&function_name)
+                .map_err(|e| e.to_string())?;
+            line_function.call();
+        }
+
+        if let StatementNode::VariableDeclaration(var_decl) = &statement {
+            session_variables.push((var_decl.name.clone(), var_decl.var_type.clone().unwrap()));
+        }
+    }
+}
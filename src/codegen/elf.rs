@@ -1,7 +1,10 @@
-use std::{process::Command, env};
+use std::{io::{self, Write}, process::{Command, Stdio}, env};
 
 pub struct MachineCodeGenerator {
     clang_path: String,
+    pub sanitize: Option<String>,
+    pub target: Option<String>,
+    pub verbose: bool,
 }
 
 impl MachineCodeGenerator {
@@ -13,40 +16,127 @@ impl MachineCodeGenerator {
         };
 
         Self {
-            clang_path: clang_env
+            clang_path: clang_env,
+            sanitize: None,
+            target: None,
+            verbose: false,
         }
     }
 
-    pub fn generate_assembly_file(&self, input_ir: &str, output_asm: &str) {
-        Command::new(&self.clang_path)
-            .arg("-S")
-            .arg(input_ir)
-            .arg("-o")
-            .arg(output_asm)
-            .arg("-Wno-override-module")
-            .status()
-            .expect("Failed to generate assembly file");
+    // Only links, so the object file has to come from somewhere else (see `CodeGenerator::write_object_file`).
+    // Falls back to the system `cc` when no clang path is set.
+    pub fn linker_only() -> Self {
+        Self {
+            clang_path: env::var("ZIGET_CLANG_PATH").unwrap_or_else(|_| "cc".to_string()),
+            sanitize: None,
+            target: None,
+            verbose: false,
+        }
     }
 
-    pub fn generate_object_file(&self, input_asm: &str, output_obj: &str) {
-        Command::new(&self.clang_path)
-            .arg("-c")
-            .arg(input_asm)
-            .arg("-o")
-            .arg(output_obj)
-            .arg("-Wno-override-module")
-            .status()
-            .expect("Failed to generate object file");
+    fn sanitize_args(&self) -> Vec<String> {
+        match &self.sanitize {
+            Some(sanitizer) => vec![format!("-fsanitize={}", sanitizer)],
+            None => Vec::new(),
+        }
+    }
+
+    fn target_args(&self) -> Vec<String> {
+        match &self.target {
+            Some(triple) => vec![format!("--target={}", triple)],
+            None => Vec::new(),
+        }
     }
 
-    pub fn link_executable(&self, input_obj: &str, output_exe: &str) {
-        Command::new(&self.clang_path)
-            .arg(input_obj)
+    // Compiles and links an empty C program with the requested sanitizer to make sure
+    // the toolchain ships its runtime before we start generating anything.
+    pub fn check_sanitizer_support(&self) -> Result<(), String> {
+        let Some(sanitizer) = &self.sanitize else {
+            return Ok(());
+        };
+
+        let mut child = Command::new(&self.clang_path)
+            .args(self.sanitize_args())
+            .arg("-x")
+            .arg("c")
+            .arg("-")
             .arg("-o")
-            .arg(output_exe)
-            .arg("-pie")
-            .arg("-lc")
-            .status()
-            .expect("Failed to link executable");
+            .arg("/dev/null")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.clang_path, e))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(b"int main(void) { return 0; }").map_err(|e| e.to_string())?;
+        }
+
+        match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(format!("{} does not support -fsanitize={}", self.clang_path, sanitizer)),
+        }
+    }
+
+    // Runs one clang step, failing with its exit status and whatever it printed to stderr so a
+    // broken build doesn't go unnoticed. Output of successful steps is only shown when verbose.
+    fn run_step(&self, command: &mut Command, step: &str) -> Result<(), String> {
+        let output = command
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", self.clang_path, e))?;
+        if output.status.success() {
+            if self.verbose {
+                io::stdout().write_all(&output.stdout).map_err(|e| e.to_string())?;
+                io::stderr().write_all(&output.stderr).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        } else {
+            Err(format!(
+                "{} failed ({}):\n{}",
+                step, output.status, String::from_utf8_lossy(&output.stderr).trim_end()
+            ))
+        }
+    }
+
+    pub fn generate_assembly_file(&self, input_ir: &str, output_asm: &str) -> Result<(), String> {
+        self.run_step(
+            Command::new(&self.clang_path)
+                .arg("-S")
+                .arg(input_ir)
+                .arg("-o")
+                .arg(output_asm)
+                .arg("-Wno-override-module")
+                .args(self.sanitize_args())
+                .args(self.target_args()),
+            "Generating the assembly file",
+        )
+    }
+
+    pub fn generate_object_file(&self, input_asm: &str, output_obj: &str) -> Result<(), String> {
+        self.run_step(
+            Command::new(&self.clang_path)
+                .arg("-c")
+                .arg(input_asm)
+                .arg("-o")
+                .arg(output_obj)
+                .arg("-Wno-override-module")
+                .args(self.sanitize_args())
+                .args(self.target_args()),
+            "Generating the object file",
+        )
+    }
+
+    pub fn link_executable(&self, input_obj: &str, output_exe: &str) -> Result<(), String> {
+        self.run_step(
+            Command::new(&self.clang_path)
+                .arg(input_obj)
+                .arg("-o")
+                .arg(output_exe)
+                .arg("-pie")
+                .arg("-lc")
+                .args(self.sanitize_args())
+                .args(self.target_args()),
+            "Linking the executable",
+        )
     }
 }
@@ -1,27 +1,41 @@
+use crate::builtins;
 use crate::lexing::token::Operator;
 use crate::parsing::node::{
     ProgramNode, ProcedureNode, MainProcedureNode, BlockNode, StatementNode, VariableDeclarationNode,
-    AssignmentNode, ReturnNode, LoopNode, ConditionalNode, ExpressionNode, BinaryOperationNode,
-    UnaryOperationNode, LiteralNode, ProcedureCallNode, TypeNode, LiteralValue,
+    DestructureNode, AssignmentNode, ReturnNode, LoopNode, ConditionalNode, ExpressionNode, BinaryOperationNode,
+    UnaryOperationNode, LiteralNode, ProcedureCallNode, TypeNode, LiteralValue, CastNode,
 };
 
 use super::ir::CodeGenerator;
 
+use inkwell::attributes::{Attribute, AttributeLoc};
+use inkwell::module::Linkage;
 use inkwell::types::BasicType;
-use inkwell::values::{BasicMetadataValueEnum, BasicValue, BasicValueEnum};
+use inkwell::values::{BasicMetadataValueEnum, BasicValue, BasicValueEnum, GlobalVisibility, IntValue};
 
 impl<'ctx> ProgramNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
+        let folded = self.folded_globals();
+        for global in &self.globals {
+            global.declare_global(generator, folded.get(&global.name));
+        }
+
+        // Declare every signature up front so a body can call procedures defined after it.
+        for procedure in &self.procedures {
+            procedure.declare(generator);
+        }
+
         for procedure in &self.procedures {
             procedure.generate_code(generator);
         }
 
-        self.main.generate_code(generator);
+        let runtime_globals: Vec<_> = self.globals.iter().filter(|global| !folded.contains_key(&global.name)).collect();
+        self.main.generate_code(generator, &runtime_globals);
     }
 }
 
 impl<'ctx> ProcedureNode {
-    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
+    pub fn declare(&self, generator: &mut CodeGenerator<'ctx>) {
         let params: Vec<_> = self.params.iter().map(|param| generator.to_basic_type(&param.param_type).into()).collect();
 
         let fn_type = match self.return_type {
@@ -29,12 +43,34 @@ impl<'ctx> ProcedureNode {
             _ => generator.to_basic_type(&self.return_type).fn_type(&params, false)
         };
 
-        let function = generator.module.add_function(&self.name, fn_type, None);
+        // Exported procedures keep their source name with external linkage so C code can call them,
+        // everything else stays private to the module.
+        let linkage = if self.export { Linkage::External } else { Linkage::Internal };
+        let function = generator.module.add_function(&self.name, fn_type, Some(linkage));
+        if self.export {
+            function.as_global_value().set_visibility(GlobalVisibility::Default);
+        }
+
+        if self.noreturn {
+            let noreturn_attribute = generator.llvm_context.create_enum_attribute(Attribute::get_named_enum_kind_id("noreturn"), 0);
+            function.add_attribute(AttributeLoc::Function, noreturn_attribute);
+        }
+    }
+
+    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
+        let function = generator.module.get_function(&self.name).expect("Procedure not declared");
         generator.function = Some(function);
+        // The parameters only exist inside this procedure.
+        let enclosing_variables = generator.variables.clone();
 
         let entry_block = generator.llvm_context.append_basic_block(function, "entry");
         generator.builder.position_at_end(entry_block);
 
+        if generator.profile {
+            let counter = generator.declare_call_counter(&self.name);
+            generator.increment_call_counter(counter);
+        }
+
         for (i, param) in self.params.iter().enumerate() {
             let alloca = generator.builder.build_alloca(generator.to_basic_type(&param.param_type), &param.name).unwrap();
             generator.builder.build_store(alloca, function.get_nth_param(i as u32).unwrap()).unwrap();
@@ -43,35 +79,78 @@ impl<'ctx> ProcedureNode {
 
         self.body.generate_code(generator);
 
-        if self.return_type == TypeNode::VoidType {
+        if generator.current_block_terminated() {
+            // The body already ended with a `yield`
+        } else if self.noreturn || self.return_type != TypeNode::VoidType {
+            // The analyzer made sure every path yields, or never finishes for noreturn procedures, so
+            // falling off the end can't happen, e.g. in the block after an unconditional loop.
+            generator.builder.build_unreachable().unwrap();
+        } else if self.return_type == TypeNode::VoidType {
             generator.builder.build_return(None).unwrap();
         }
 
+        generator.variables = enclosing_variables;
         generator.function = None;
     }
 }
 
 impl<'ctx> MainProcedureNode {
-    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
-        let fn_type = generator.llvm_context.void_type().fn_type(&[], false);
-        let function = generator.module.add_function("main", fn_type, None);
+    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>, globals: &[&VariableDeclarationNode]) {
+        // With a parameter, main takes C's `argc` and `argv` and hands the parameter the number of
+        // arguments after the program name.
+        let fn_type = if self.params.is_empty() {
+            generator.llvm_context.void_type().fn_type(&[], false)
+        } else {
+            let ptr_type = generator.llvm_context.ptr_type(inkwell::AddressSpace::default());
+            generator.llvm_context.void_type().fn_type(&[generator.llvm_context.i32_type().into(), ptr_type.into()], false)
+        };
+        let function = generator.module.add_function(&self.name, fn_type, None);
         generator.function = Some(function);
 
         let entry_block = generator.llvm_context.append_basic_block(function, "entry");
         generator.builder.position_at_end(entry_block);
 
+        // Constants that couldn't be computed at compile time get their values before anything else
+        // runs, in the order they were declared.
+        for global in globals {
+            let init_val = global.initializer.generate_code(generator).unwrap();
+            generator.builder.build_store(generator.variables[&global.name].0, init_val).unwrap();
+        }
+
+        if let Some(param) = self.params.first() {
+            let argc = function.get_nth_param(0).unwrap().into_int_value();
+            let arg_count = generator.builder.build_int_sub(argc, argc.get_type().const_int(1, false), "argcount").unwrap();
+            let arg_count = generator.builder.build_signed_int_to_float(arg_count, generator.llvm_context.f64_type(), "argcountf").unwrap();
+            let alloca = generator.builder.build_alloca(generator.to_basic_type(&param.param_type), &param.name).unwrap();
+            generator.builder.build_store(alloca, arg_count).unwrap();
+            generator.variables.insert(param.name.clone(), (alloca, param.param_type.clone()));
+        }
+
+        if generator.profile {
+            generator.register_profile_dump();
+        }
+
         self.body.generate_code(generator);
 
-        generator.builder.build_return(None).unwrap();
+        if !generator.current_block_terminated() {
+            generator.builder.build_return(None).unwrap();
+        }
         generator.function = None;
     }
 }
 
 impl<'ctx> BlockNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
+        // Variables declared in the block, including ones shadowing an outer variable, go out of scope with it.
+        let enclosing_variables = generator.variables.clone();
         for statement in &self.statements {
+            // Anything after a `yield`, `leave` or `repeat` is unreachable.
+            if generator.current_block_terminated() {
+                break;
+            }
             statement.generate_code(generator);
         }
+        generator.variables = enclosing_variables;
     }
 }
 
@@ -79,19 +158,20 @@ impl<'ctx> StatementNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
         match self {
             StatementNode::VariableDeclaration(var_decl) => var_decl.generate_code(generator),
+            StatementNode::Destructure(destructure) => destructure.generate_code(generator),
             StatementNode::Assignment(assign) => assign.generate_code(generator),
             StatementNode::Expression(expr) => { expr.generate_code(generator); },
             StatementNode::Return(ret) => ret.generate_code(generator),
             StatementNode::Loop(loop_node) => loop_node.generate_code(generator),
             StatementNode::Conditional(cond) => cond.generate_code(generator),
-            StatementNode::Break => {
+            StatementNode::Break(_) => {
                 if let Some(loop_end) = generator.loop_end_block {
                     generator.builder.build_unconditional_branch(loop_end).unwrap();
                 } else {
                     panic!("`leave` statement used outside of a loop context");
                 }
             },
-            StatementNode::Continue => {
+            StatementNode::Continue(_) => {
                 if let Some(loop_header) = generator.loop_start_block {
                     generator.builder.build_unconditional_branch(loop_header).unwrap();
                 } else {
@@ -103,6 +183,29 @@ impl<'ctx> StatementNode {
 }
 
 impl<'ctx> VariableDeclarationNode {
+    // A top-level constant becomes a global holding its folded value, so exported procedures called
+    // without main see it too. Otherwise it is zero-initialized and main fills it in when the program starts.
+    pub fn declare_global(&self, generator: &mut CodeGenerator<'ctx>, folded: Option<&LiteralValue>) {
+        let var_type = generator.to_basic_type(self.var_type.as_ref().unwrap());
+        let global = generator.module.add_global(var_type, None, &format!("global.{}", self.name));
+        global.set_linkage(Linkage::Internal);
+        match folded {
+            Some(LiteralValue::NumberValue(n)) => global.set_initializer(&generator.llvm_context.f64_type().const_float(*n)),
+            Some(LiteralValue::BooleanValue(b)) => global.set_initializer(&generator.llvm_context.bool_type().const_int(*b as u64, false)),
+            Some(LiteralValue::StringValue(s)) => {
+                let text = generator.llvm_context.const_string(s.as_bytes(), true);
+                let text_global = generator.module.add_global(text.get_type(), None, &format!("global.{}.str", self.name));
+                text_global.set_linkage(Linkage::Private);
+                text_global.set_constant(true);
+                text_global.set_initializer(&text);
+                global.set_initializer(&text_global.as_pointer_value());
+            }
+            None => global.set_initializer(&var_type.const_zero()),
+        }
+        global.set_constant(folded.is_some());
+        generator.variables.insert(self.name.clone(), (global.as_pointer_value(), self.var_type.clone().unwrap()));
+    }
+
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
         let var_type = generator.to_basic_type(self.var_type.as_ref().unwrap());
         let alloca = generator.builder.build_alloca(var_type, &self.name).unwrap();
@@ -112,12 +215,25 @@ impl<'ctx> VariableDeclarationNode {
     }
 }
 
+impl<'ctx> DestructureNode {
+    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
+        let tuple = self.value.generate_code(generator).unwrap().into_struct_value();
+        for (i, (name, var_type)) in self.names.iter().zip(&self.var_types).enumerate() {
+            let alloca = generator.builder.build_alloca(generator.to_basic_type(var_type), name).unwrap();
+            let value = generator.builder.build_extract_value(tuple, i as u32, name).unwrap();
+            generator.builder.build_store(alloca, value).unwrap();
+            generator.variables.insert(name.clone(), (alloca, var_type.clone()));
+        }
+    }
+}
+
 impl<'ctx> AssignmentNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
-        if let Some(var) = generator.variables.get(&self.name) {
-            let var_clone = (*var).clone();
-            let val = self.value.generate_code(generator).unwrap();
-            generator.builder.build_store(var_clone.0, val).unwrap();
+        let val = self.value.generate_code(generator).unwrap();
+        for name in &self.targets {
+            if let Some(var) = generator.variables.get(name) {
+                generator.builder.build_store(var.0, val).unwrap();
+            }
         }
     }
 }
@@ -135,46 +251,168 @@ impl<'ctx> ReturnNode {
 
 impl<'ctx> LoopNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
-        let loop_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "loop");
-        let after_loop_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "afterloop");
+        let function = generator.function.unwrap();
+        // `loop while`, `loop N times` and `loop i from A to B` re-check their condition in a block of
+        // their own before every iteration.
+        let condition_bb = (self.condition.is_some() || self.count.is_some() || self.range.is_some())
+            .then(|| generator.llvm_context.append_basic_block(function, "loopcond"));
+        let loop_bb = generator.llvm_context.append_basic_block(function, "loop");
+        // `loop N times` and `loop i from A to B` count the finished iteration before checking the condition again.
+        let increment_bb = (self.count.is_some() || self.range.is_some())
+            .then(|| generator.llvm_context.append_basic_block(function, "loopinc"));
+        // A loop that finishes on its own goes through its `otherwise` block, one exited with `leave` skips it.
+        let otherwise_bb = self.otherwise.as_ref()
+            .map(|_| generator.llvm_context.append_basic_block(function, "loopotherwise"));
+        let after_loop_bb = generator.llvm_context.append_basic_block(function, "afterloop");
+        let completed_bb = otherwise_bb.unwrap_or(after_loop_bb);
+        let entry_bb = condition_bb.unwrap_or(loop_bb);
+        let start_bb = increment_bb.unwrap_or(entry_bb);
+
+        // `repeat` branches to `loop_start_block`, so a loop that needs work done before the next
+        // iteration must point it there instead of at the top of the body.
+        let enclosing_start_block = generator.loop_start_block.replace(start_bb);
+        let enclosing_end_block = generator.loop_end_block.replace(after_loop_bb);
+
+        // Every entry into the loop starts counting from zero again.
+        let iteration_counter = generator.max_loop_iterations.map(|_| {
+            let i64_type = generator.llvm_context.i64_type();
+            let counter = generator.build_entry_alloca(i64_type, "loopiterations");
+            generator.builder.build_store(counter, i64_type.const_zero()).unwrap();
+            counter
+        });
+
+        // The count is evaluated once and truncated to a whole number, so `loop 2.5 times` runs
+        // twice and a count of zero or less skips the body.
+        let times = self.count.as_ref().map(|count| {
+            let i64_type = generator.llvm_context.i64_type();
+            let count_val = count.generate_code(generator).unwrap().into_float_value();
+            let times = generator.builder.build_float_to_signed_int(count_val, i64_type, "looptimes").unwrap();
+            let index = generator.build_entry_alloca(i64_type, "loopindex");
+            generator.builder.build_store(index, i64_type.const_zero()).unwrap();
+            (index, times)
+        });
+
+        // The bounds are evaluated once as well, and the counter starts at the lower one.
+        let range_counter = self.range.as_ref().map(|range| {
+            let f64_type = generator.llvm_context.f64_type();
+            let from_val = range.from.generate_code(generator).unwrap();
+            let to_val = range.to.generate_code(generator).unwrap().into_float_value();
+            let counter = generator.build_entry_alloca(f64_type, &range.variable);
+            generator.builder.build_store(counter, from_val).unwrap();
+            (counter, to_val)
+        });
+
+        generator.builder.build_unconditional_branch(entry_bb).unwrap();
+
+        if let (Some(condition), Some(condition_bb)) = (&self.condition, condition_bb) {
+            generator.builder.position_at_end(condition_bb);
+            let condition_val = condition.generate_code(generator).unwrap().into_int_value();
+            generator.builder.build_conditional_branch(condition_val, loop_bb, completed_bb).unwrap();
+        }
+
+        if let (Some((index, times)), Some(condition_bb), Some(increment_bb)) = (times, condition_bb, increment_bb) {
+            let i64_type = generator.llvm_context.i64_type();
 
-        generator.loop_start_block = Some(loop_bb);
-        generator.loop_end_block = Some(after_loop_bb);
+            generator.builder.position_at_end(condition_bb);
+            let index_val = generator.builder.build_load(i64_type, index, "loopindexval").unwrap().into_int_value();
+            let remaining = generator.builder.build_int_compare(inkwell::IntPredicate::SLT, index_val, times, "loopremaining").unwrap();
+            generator.builder.build_conditional_branch(remaining, loop_bb, completed_bb).unwrap();
+
+            generator.builder.position_at_end(increment_bb);
+            let index_val = generator.builder.build_load(i64_type, index, "loopindexval").unwrap().into_int_value();
+            let next_index = generator.builder.build_int_add(index_val, i64_type.const_int(1, false), "loopnextindex").unwrap();
+            generator.builder.build_store(index, next_index).unwrap();
+            generator.builder.build_unconditional_branch(condition_bb).unwrap();
+        }
+
+        if let (Some(range), Some((counter, to_val)), Some(condition_bb), Some(increment_bb)) = (&self.range, range_counter, condition_bb, increment_bb) {
+            let f64_type = generator.llvm_context.f64_type();
+
+            generator.builder.position_at_end(condition_bb);
+            let counter_val = generator.builder.build_load(f64_type, counter, "loopcounterval").unwrap().into_float_value();
+            let predicate = if range.inclusive { inkwell::FloatPredicate::OLE } else { inkwell::FloatPredicate::OLT };
+            let in_range = generator.builder.build_float_compare(predicate, counter_val, to_val, "loopinrange").unwrap();
+            generator.builder.build_conditional_branch(in_range, loop_bb, completed_bb).unwrap();
+
+            generator.builder.position_at_end(increment_bb);
+            let counter_val = generator.builder.build_load(f64_type, counter, "loopcounterval").unwrap().into_float_value();
+            let next_counter = generator.builder.build_float_add(counter_val, f64_type.const_float(1.0), "loopnextcounter").unwrap();
+            generator.builder.build_store(counter, next_counter).unwrap();
+            generator.builder.build_unconditional_branch(condition_bb).unwrap();
+        }
 
-        generator.builder.build_unconditional_branch(loop_bb).unwrap();
         generator.builder.position_at_end(loop_bb);
 
+        if let (Some(counter), Some(limit)) = (iteration_counter, generator.max_loop_iterations) {
+            generator.check_loop_iterations(counter, limit);
+        }
+
+        // The counter is only visible in the body.
+        let enclosing_variables = generator.variables.clone();
+        if let (Some(range), Some((counter, _))) = (&self.range, range_counter) {
+            generator.variables.insert(range.variable.clone(), (counter, TypeNode::NumberType));
+        }
+
         self.body.generate_code(generator);
+        generator.variables = enclosing_variables;
 
-        generator.builder.build_unconditional_branch(loop_bb).unwrap();
-        generator.builder.position_at_end(after_loop_bb);
+        if !generator.current_block_terminated() {
+            generator.builder.build_unconditional_branch(start_bb).unwrap();
+        }
+
+        generator.loop_start_block = enclosing_start_block;
+        generator.loop_end_block = enclosing_end_block;
 
-        generator.loop_start_block = None;
-        generator.loop_end_block = None;
+        if let (Some(otherwise), Some(otherwise_bb)) = (&self.otherwise, otherwise_bb) {
+            generator.builder.position_at_end(otherwise_bb);
+            otherwise.generate_code(generator);
+            if !generator.current_block_terminated() {
+                generator.builder.build_unconditional_branch(after_loop_bb).unwrap();
+            }
+        }
+        generator.builder.position_at_end(after_loop_bb);
     }
 }
 
 impl<'ctx> ConditionalNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) {
-        let condition_val = self.condition.generate_code(generator).unwrap();
-        let condition_bool = generator.builder.build_int_compare(inkwell::IntPredicate::EQ, condition_val.into_int_value(), generator.llvm_context.bool_type().const_int(1, false), "cond");
+        // Booleans are `i1` everywhere, whether they come from a literal, a comparison, `and`/`or`
+        // or a variable, so the value can be branched on directly, like loop conditions are.
+        let condition_val = self.condition.generate_code(generator).unwrap().into_int_value();
 
         let then_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "then");
         let else_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "else");
-        let merge_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "merge");
 
-        generator.builder.build_conditional_branch(condition_bool.unwrap(), then_bb, else_bb).unwrap();
+        generator.builder.build_conditional_branch(condition_val, then_bb, else_bb).unwrap();
+
+        // Blocks where a branch ends without having returned or jumped elsewhere; only these fall through to the merge block.
+        let mut open_blocks = Vec::new();
 
         generator.builder.position_at_end(then_bb);
         self.consequence.generate_code(generator);
-        generator.builder.build_unconditional_branch(merge_bb).unwrap();
+        if !generator.current_block_terminated() {
+            open_blocks.push(generator.builder.get_insert_block().unwrap());
+        }
 
         generator.builder.position_at_end(else_bb);
         if let Some(alternative) = &self.alternative {
             alternative.generate_code(generator);
         }
-        generator.builder.build_unconditional_branch(merge_bb).unwrap();
+        if !generator.current_block_terminated() {
+            open_blocks.push(generator.builder.get_insert_block().unwrap());
+        }
+
+        // When both branches terminate there is nothing to merge, and the builder stays in a
+        // terminated block so the rest of the enclosing block is skipped as unreachable.
+        if open_blocks.is_empty() {
+            return;
+        }
 
+        let merge_bb = generator.llvm_context.append_basic_block(generator.function.unwrap(), "merge");
+        for block in open_blocks {
+            generator.builder.position_at_end(block);
+            generator.builder.build_unconditional_branch(merge_bb).unwrap();
+        }
         generator.builder.position_at_end(merge_bb);
     }
 }
@@ -185,7 +423,7 @@ impl<'ctx> ExpressionNode {
             ExpressionNode::BinaryOperation(bin_op) => bin_op.generate_code(generator),
             ExpressionNode::UnaryOperation(un_op) => un_op.generate_code(generator),
             ExpressionNode::Literal(lit) => lit.generate_code(generator),
-            ExpressionNode::Variable(var_name) => {
+            ExpressionNode::Variable(var_name, _) => {
                 if let Some(var) = generator.variables.get(var_name) {
                     Some(generator.builder.build_load(generator.to_basic_type(&(*var).1),(*var).0, var_name).unwrap())
                 } else {
@@ -193,14 +431,33 @@ impl<'ctx> ExpressionNode {
                 }
             }
             ExpressionNode::ProcedureCall(proc_call) => proc_call.generate_code(generator),
+            ExpressionNode::Cast(cast) => cast.generate_code(generator),
+            ExpressionNode::ArrayLiteral(elements, _) => {
+                let values = elements.iter().map(|element| element.generate_code(generator)).collect::<Option<Vec<_>>>()?;
+                let array_type = values.first()?.get_type().array_type(values.len() as u32);
+                let mut array = array_type.get_undef();
+                for (i, value) in values.into_iter().enumerate() {
+                    array = generator.builder.build_insert_value(array, value, i as u32, "arraytmp").unwrap().into_array_value();
+                }
+                Some(array.as_basic_value_enum())
+            }
+            ExpressionNode::Tuple(values, _) => {
+                let values = values.iter().map(|value| value.generate_code(generator)).collect::<Option<Vec<_>>>()?;
+                let field_types: Vec<_> = values.iter().map(|value| value.get_type()).collect();
+                let mut tuple = generator.llvm_context.struct_type(&field_types, false).get_undef();
+                for (i, value) in values.into_iter().enumerate() {
+                    tuple = generator.builder.build_insert_value(tuple, value, i as u32, "tupletmp").unwrap().into_struct_value();
+                }
+                Some(tuple.as_basic_value_enum())
+            }
         }
     }
 }
 
 impl<'ctx> ProcedureCallNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
-        if self.name == "print" {
-            return self.generate_print_code(generator);
+        if let Some(builtin) = builtins::find(&self.name) {
+            return (builtin.generate)(self, generator);
         }
         let function = generator.module.get_function(&self.name).expect("Unknown function called");
 
@@ -212,8 +469,70 @@ impl<'ctx> ProcedureCallNode {
 
         call.unwrap().try_as_basic_value().left()
     }
+}
+
+// Builtin code generators are stored as `for<'ctx> fn` pointers in the builtin registry,
+// so their lifetime has to be declared on the method rather than on the impl.
+impl ProcedureCallNode {
+    // Reads a number from stdin, falling back to the argument when scanf can't parse one.
+    pub(crate) fn generate_read_or_code<'ctx>(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        let scanf_function = generator.module.get_function("scanf")
+            .expect("scanf function not declared");
+        let f64_type = generator.llvm_context.f64_type();
+
+        let default_value = self.args[0].generate_code(generator).unwrap();
+        let input_slot = generator.builder.build_alloca(f64_type, "input").unwrap();
+        let format_string = generator.builder.build_global_string_ptr("%lf", "scanfmt").unwrap();
+
+        let matched = generator.builder.build_call(scanf_function, &[format_string.as_pointer_value().into(), input_slot.into()], "scantmp")
+            .unwrap().try_as_basic_value().left().unwrap();
+        let parsed = generator.builder.build_int_compare(inkwell::IntPredicate::EQ, matched.into_int_value(), generator.llvm_context.i32_type().const_int(1, false), "parsed").unwrap();
+
+        let input_value = generator.builder.build_load(f64_type, input_slot, "inputval").unwrap();
+        let result = generator.builder.build_select(parsed, input_value, default_value, "readtmp").unwrap();
+
+        Some(result)
+    }
+
+    pub(crate) fn generate_flush_code<'ctx>(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        generator.flush_stdout();
+        None
+    }
+
+    // Formats into a buffer sized by a first snprintf call that only measures the output.
+    pub(crate) fn generate_format_code<'ctx>(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        let snprintf_function = generator.module.get_function("snprintf")
+            .expect("snprintf function not declared");
+        let malloc_function = generator.module.get_function("malloc")
+            .expect("malloc function not declared");
+        let ptr_type = generator.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let i64_type = generator.llvm_context.i64_type();
+
+        let mut format_args: Vec<BasicMetadataValueEnum<'ctx>> = Vec::new();
+        for expr in &self.args {
+            let generated_arg = expr.generate_code(generator).unwrap();
+            format_args.push(generator.promote_variadic_arg(generated_arg).into());
+        }
+
+        let mut measure_args: Vec<BasicMetadataValueEnum<'ctx>> = vec![ptr_type.const_null().into(), i64_type.const_zero().into()];
+        measure_args.extend(format_args.iter().copied());
+        let length = generator.builder.build_call(snprintf_function, &measure_args, "formatlen")
+            .unwrap().try_as_basic_value().left().unwrap().into_int_value();
+
+        // One more byte for the terminating null.
+        let length = generator.builder.build_int_s_extend(length, i64_type, "formatlen64").unwrap();
+        let size = generator.builder.build_int_add(length, i64_type.const_int(1, false), "formatsize").unwrap();
+        let buffer = generator.builder.build_call(malloc_function, &[size.into()], "formatbuf")
+            .unwrap().try_as_basic_value().left().unwrap();
+
+        let mut write_args: Vec<BasicMetadataValueEnum<'ctx>> = vec![buffer.into(), size.into()];
+        write_args.extend(format_args);
+        generator.builder.build_call(snprintf_function, &write_args, "formattmp").unwrap();
+
+        Some(buffer)
+    }
 
-    fn generate_print_code(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+    pub(crate) fn generate_print_code<'ctx>(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
         let printf_function = generator.module.get_function("printf")
             .expect("printf function not declared");
 
@@ -225,10 +544,13 @@ impl<'ctx> ProcedureCallNode {
 
         for expr in self.args.iter().skip(1) {
             let generated_arg = expr.generate_code(generator).unwrap();
-            args.push(generated_arg.into());
+            args.push(generator.promote_variadic_arg(generated_arg).into());
         }
 
         generator.builder.build_call(printf_function, &args, "printtmp").unwrap();
+        if generator.auto_flush {
+            generator.flush_stdout();
+        }
         
         None
     }
@@ -236,6 +558,13 @@ impl<'ctx> ProcedureCallNode {
 
 impl<'ctx> BinaryOperationNode {
     pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        if let Operator::And | Operator::Or = self.operator {
+            return self.generate_short_circuit_code(generator);
+        }
+        if let Some(result) = self.generate_boolean_literal_comparison(generator) {
+            return result;
+        }
+
         let lhs = self.left.generate_code(generator)?;
         let rhs = self.right.generate_code(generator)?;
 
@@ -244,13 +573,31 @@ impl<'ctx> BinaryOperationNode {
             Operator::Minus => generator.builder.build_float_sub(lhs.into_float_value(), rhs.into_float_value(), "subtmp").unwrap().as_basic_value_enum(),
             Operator::Times => generator.builder.build_float_mul(lhs.into_float_value(), rhs.into_float_value(), "multmp").unwrap().as_basic_value_enum(),
             Operator::Over => generator.builder.build_float_div(lhs.into_float_value(), rhs.into_float_value(), "divtmp").unwrap().as_basic_value_enum(),
+            // Numbers are all floats, so floor division rounds the quotient down instead of dividing integers.
+            Operator::FloorOver => {
+                let quotient = generator.builder.build_float_div(lhs.into_float_value(), rhs.into_float_value(), "divtmp").unwrap();
+                let floor_function = generator.module.get_function("llvm.floor.f64").expect("llvm.floor.f64 intrinsic not declared");
+                generator.builder.build_call(floor_function, &[quotient.into()], "floordivtmp").unwrap().try_as_basic_value().left().unwrap()
+            }
             Operator::Mod => generator.builder.build_float_rem(lhs.into_float_value(), rhs.into_float_value(), "modtmp").unwrap().as_basic_value_enum(),
+            Operator::Power => {
+                let pow_function = generator.module.get_function("llvm.pow.f64").expect("llvm.pow.f64 intrinsic not declared");
+                generator.builder.build_call(pow_function, &[lhs.into(), rhs.into()], "powtmp").unwrap().try_as_basic_value().left().unwrap()
+            }
 
-            Operator::And => generator.builder.build_and(lhs.into_int_value(), rhs.into_int_value(), "andtmp").unwrap().as_basic_value_enum(),
-            Operator::Or => generator.builder.build_or(lhs.into_int_value(), rhs.into_int_value(), "ortmp").unwrap().as_basic_value_enum(),
-
-            Operator::Is => generator.builder.build_float_compare(inkwell::FloatPredicate::OEQ, lhs.into_float_value(), rhs.into_float_value(), "eqtmp").unwrap().as_basic_value_enum(),
-            Operator::Isnt => generator.builder.build_float_compare(inkwell::FloatPredicate::ONE, lhs.into_float_value(), rhs.into_float_value(), "netmp").unwrap().as_basic_value_enum(),
+            Operator::Is => Self::generate_equality_code(generator, lhs, rhs).as_basic_value_enum(),
+            Operator::Isnt => match (lhs, rhs) {
+                (BasicValueEnum::FloatValue(l), BasicValueEnum::FloatValue(r)) => {
+                    generator.builder.build_float_compare(inkwell::FloatPredicate::ONE, l, r, "netmp").unwrap().as_basic_value_enum()
+                }
+                (BasicValueEnum::IntValue(l), BasicValueEnum::IntValue(r)) => {
+                    generator.builder.build_int_compare(inkwell::IntPredicate::NE, l, r, "netmp").unwrap().as_basic_value_enum()
+                }
+                _ => {
+                    let equal = Self::generate_equality_code(generator, lhs, rhs);
+                    generator.builder.build_not(equal, "netmp").unwrap().as_basic_value_enum()
+                }
+            },
             Operator::Lt => generator.builder.build_float_compare(inkwell::FloatPredicate::OLT, lhs.into_float_value(), rhs.into_float_value(), "lttmp").unwrap().as_basic_value_enum(),
             Operator::Gt => generator.builder.build_float_compare(inkwell::FloatPredicate::OGT, lhs.into_float_value(), rhs.into_float_value(), "gttmp").unwrap().as_basic_value_enum(),
             Operator::Lte => generator.builder.build_float_compare(inkwell::FloatPredicate::OLE, lhs.into_float_value(), rhs.into_float_value(), "ltetmp").unwrap().as_basic_value_enum(),
@@ -260,6 +607,129 @@ impl<'ctx> BinaryOperationNode {
 
         Some(result)
     }
+
+    // `x is yes` and `x isnt no` are just `x`, while `x isnt yes` and `x is no` are `not x`,
+    // so comparing with a boolean literal needs no compare instruction at all.
+    fn generate_boolean_literal_comparison(&self, generator: &mut CodeGenerator<'ctx>) -> Option<Option<BasicValueEnum<'ctx>>> {
+        let literal_value = |expr: &ExpressionNode| match expr {
+            ExpressionNode::Literal(LiteralNode { value: LiteralValue::BooleanValue(b), .. }) => Some(*b),
+            _ => None,
+        };
+        let (operand, literal) = match (literal_value(&self.left), literal_value(&self.right)) {
+            (_, Some(literal)) => (&self.left, literal),
+            (Some(literal), None) => (&self.right, literal),
+            (None, None) => return None,
+        };
+        let negate = match self.operator {
+            Operator::Is => !literal,
+            Operator::Isnt => literal,
+            _ => return None,
+        };
+
+        let value = operand.generate_code(generator);
+        if !negate {
+            return Some(value);
+        }
+        Some(value.map(|value| generator.builder.build_not(value.into_int_value(), "nottmp").unwrap().as_basic_value_enum()))
+    }
+
+    // Compares numbers, booleans and arrays of them. Arrays are walked element by element
+    // in a loop that stops at the first mismatch.
+    fn generate_equality_code(generator: &mut CodeGenerator<'ctx>, lhs: BasicValueEnum<'ctx>, rhs: BasicValueEnum<'ctx>) -> IntValue<'ctx> {
+        match (lhs, rhs) {
+            (BasicValueEnum::FloatValue(l), BasicValueEnum::FloatValue(r)) => {
+                generator.builder.build_float_compare(inkwell::FloatPredicate::OEQ, l, r, "eqtmp").unwrap()
+            }
+            (BasicValueEnum::IntValue(l), BasicValueEnum::IntValue(r)) => {
+                generator.builder.build_int_compare(inkwell::IntPredicate::EQ, l, r, "eqtmp").unwrap()
+            }
+            // Strings are compared by content, not by address.
+            (BasicValueEnum::PointerValue(l), BasicValueEnum::PointerValue(r)) => {
+                let strcmp_function = generator.module.get_function("strcmp").expect("strcmp function not declared");
+                let order = generator.builder.build_call(strcmp_function, &[l.into(), r.into()], "strcmptmp")
+                    .unwrap().try_as_basic_value().left().unwrap().into_int_value();
+                generator.builder.build_int_compare(inkwell::IntPredicate::EQ, order, generator.llvm_context.i32_type().const_zero(), "eqtmp").unwrap()
+            }
+            (BasicValueEnum::ArrayValue(l), BasicValueEnum::ArrayValue(r)) => {
+                let function = generator.function.unwrap();
+                let array_type = l.get_type();
+                let element_type = array_type.get_element_type();
+                let i64_type = generator.llvm_context.i64_type();
+                let bool_type = generator.llvm_context.bool_type();
+
+                let lhs_ptr = generator.builder.build_alloca(array_type, "lhsarray").unwrap();
+                let rhs_ptr = generator.builder.build_alloca(array_type, "rhsarray").unwrap();
+                generator.builder.build_store(lhs_ptr, l).unwrap();
+                generator.builder.build_store(rhs_ptr, r).unwrap();
+
+                let entry_block = generator.builder.get_insert_block().unwrap();
+                let header_block = generator.llvm_context.append_basic_block(function, "cmpheader");
+                let body_block = generator.llvm_context.append_basic_block(function, "cmpbody");
+                let next_block = generator.llvm_context.append_basic_block(function, "cmpnext");
+                let done_block = generator.llvm_context.append_basic_block(function, "cmpdone");
+
+                generator.builder.build_unconditional_branch(header_block).unwrap();
+
+                generator.builder.position_at_end(header_block);
+                let index = generator.builder.build_phi(i64_type, "cmpindex").unwrap();
+                index.add_incoming(&[(&i64_type.const_zero(), entry_block)]);
+                let index_value = index.as_basic_value().into_int_value();
+                let length = i64_type.const_int(array_type.len() as u64, false);
+                let in_bounds = generator.builder.build_int_compare(inkwell::IntPredicate::ULT, index_value, length, "cmpinbounds").unwrap();
+                generator.builder.build_conditional_branch(in_bounds, body_block, done_block).unwrap();
+
+                generator.builder.position_at_end(body_block);
+                let indices = [i64_type.const_zero(), index_value];
+                let lhs_element_ptr = unsafe { generator.builder.build_in_bounds_gep(array_type, lhs_ptr, &indices, "lhselementptr").unwrap() };
+                let rhs_element_ptr = unsafe { generator.builder.build_in_bounds_gep(array_type, rhs_ptr, &indices, "rhselementptr").unwrap() };
+                let lhs_element = generator.builder.build_load(element_type, lhs_element_ptr, "lhselement").unwrap();
+                let rhs_element = generator.builder.build_load(element_type, rhs_element_ptr, "rhselement").unwrap();
+                let elements_equal = Self::generate_equality_code(generator, lhs_element, rhs_element);
+                let body_end_block = generator.builder.get_insert_block().unwrap();
+                generator.builder.build_conditional_branch(elements_equal, next_block, done_block).unwrap();
+
+                generator.builder.position_at_end(next_block);
+                let next_index = generator.builder.build_int_add(index_value, i64_type.const_int(1, false), "cmpnextindex").unwrap();
+                index.add_incoming(&[(&next_index, next_block)]);
+                generator.builder.build_unconditional_branch(header_block).unwrap();
+
+                generator.builder.position_at_end(done_block);
+                let result = generator.builder.build_phi(bool_type, "arrayeqtmp").unwrap();
+                result.add_incoming(&[(&bool_type.const_int(1, false), header_block), (&bool_type.const_zero(), body_end_block)]);
+                result.as_basic_value().into_int_value()
+            }
+            _ => unreachable!("the analyzer only lets `is` compare two values of the same number, boolean, string or array type (see is_comparable)"),
+        }
+    }
+
+    // `and`/`or` only evaluate the right operand when the left one doesn't already decide the result.
+    // When it does, that value is the result: `no` for `and`, `yes` for `or`.
+    fn generate_short_circuit_code(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        let function = generator.function.unwrap();
+
+        let lhs = self.left.generate_code(generator)?.into_int_value();
+        let lhs_block = generator.builder.get_insert_block().unwrap();
+
+        let rhs_block = generator.llvm_context.append_basic_block(function, "rhs");
+        let merge_block = generator.llvm_context.append_basic_block(function, "shortcircuit");
+
+        if self.operator == Operator::And {
+            generator.builder.build_conditional_branch(lhs, rhs_block, merge_block).unwrap();
+        } else {
+            generator.builder.build_conditional_branch(lhs, merge_block, rhs_block).unwrap();
+        }
+
+        generator.builder.position_at_end(rhs_block);
+        let rhs = self.right.generate_code(generator)?.into_int_value();
+        let rhs_end_block = generator.builder.get_insert_block().unwrap();
+        generator.builder.build_unconditional_branch(merge_block).unwrap();
+
+        generator.builder.position_at_end(merge_block);
+        let phi = generator.builder.build_phi(lhs.get_type(), "logictmp").unwrap();
+        phi.add_incoming(&[(&lhs, lhs_block), (&rhs, rhs_end_block)]);
+
+        Some(phi.as_basic_value())
+    }
 }
 
 impl<'ctx> UnaryOperationNode {
@@ -267,11 +737,30 @@ impl<'ctx> UnaryOperationNode {
         let operand = self.operand.generate_code(generator)?;
 
         let result = match self.operator {
-            Operator::Minus => generator.builder.build_float_neg(operand.into_float_value(), "negtmp"),
+            Operator::Minus => generator.builder.build_float_neg(operand.into_float_value(), "negtmp").unwrap().as_basic_value_enum(),
+            Operator::Not => generator.builder.build_not(operand.into_int_value(), "nottmp").unwrap().as_basic_value_enum(),
+            Operator::Plus => operand,
             _ => unimplemented!(),
         };
 
-        Some(result.unwrap().as_basic_value_enum())
+        Some(result)
+    }
+}
+
+impl<'ctx> CastNode {
+    // Both conversions are branchless: number -> boolean is a single `fcmp one n, 0.0`
+    // and boolean -> number is a single `uitofp i1 to double`.
+    pub fn generate_code(&self, generator: &mut CodeGenerator<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        let operand = self.operand.generate_code(generator)?;
+        let f64_type = generator.llvm_context.f64_type();
+
+        let result = match (&self.target_type, operand) {
+            (TypeNode::BooleanType, BasicValueEnum::FloatValue(n)) => generator.builder.build_float_compare(inkwell::FloatPredicate::ONE, n, f64_type.const_float(0.0), "booltmp").unwrap().as_basic_value_enum(),
+            (TypeNode::NumberType, BasicValueEnum::IntValue(b)) => generator.builder.build_unsigned_int_to_float(b, f64_type, "numtmp").unwrap().as_basic_value_enum(),
+            _ => operand,
+        };
+
+        Some(result)
     }
 }
 
@@ -1,11 +1,15 @@
 use inkwell::context::Context;
 use inkwell::builder::Builder;
 use inkwell::module::Module;
-use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple};
+use inkwell::OptimizationLevel;
+use inkwell::values::{BasicValueEnum, FunctionValue, GlobalValue, PointerValue};
 use inkwell::types::{BasicType, BasicTypeEnum, VoidType};
 use std::collections::HashMap;
+use std::path::Path;
 
-use crate::parsing::node::{ProgramNode, TypeNode};
+use crate::parsing::node::{ProgramNode, StatementNode, TypeNode};
 
 
 pub struct CodeGenerator<'ctx> {
@@ -16,6 +20,11 @@ pub struct CodeGenerator<'ctx> {
     pub variables: HashMap<String, (PointerValue<'ctx>, TypeNode)>,
     pub loop_start_block: Option<inkwell::basic_block::BasicBlock<'ctx>>,
     pub loop_end_block: Option<inkwell::basic_block::BasicBlock<'ctx>>,
+    pub profile: bool,
+    pub call_counters: Vec<(String, GlobalValue<'ctx>)>,
+    pub target: Option<String>,
+    pub auto_flush: bool,
+    pub max_loop_iterations: Option<u64>,
 }
 
 impl<'ctx> CodeGenerator<'ctx> {
@@ -30,19 +39,123 @@ impl<'ctx> CodeGenerator<'ctx> {
             function: None,
             variables: HashMap::new(),
             loop_start_block: None,
-            loop_end_block: None
+            loop_end_block: None,
+            profile: false,
+            call_counters: Vec::new(),
+            target: None,
+            auto_flush: false,
+            max_loop_iterations: None,
         }
     }
 
     pub fn generate_code(&mut self, program: &ProgramNode) {
-        self.declare_printf();
+        self.declare_runtime();
         program.generate_code(self);
     }
 
+    // Generates one REPL line as a procedure of its own in this module. Variables declared by
+    // earlier lines are globals defined in earlier modules, so they are only declared here and
+    // the JIT links them up.
+    pub fn generate_repl_line(&mut self, function_name: &str, statement: &StatementNode, session_variables: &[(String, TypeNode)]) {
+        self.declare_runtime();
+        for (name, var_type) in session_variables {
+            let global = self.module.add_global(self.to_basic_type(var_type), None, &format!("repl.{}", name));
+            self.variables.insert(name.clone(), (global.as_pointer_value(), var_type.clone()));
+        }
+
+        let function = self.module.add_function(function_name, self.void_type().fn_type(&[], false), None);
+        self.function = Some(function);
+        let entry = self.llvm_context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+
+        match statement {
+            StatementNode::VariableDeclaration(var_decl) => {
+                let var_type = var_decl.var_type.clone().unwrap();
+                let basic_type = self.to_basic_type(&var_type);
+                let global = self.module.add_global(basic_type, None, &format!("repl.{}", var_decl.name));
+                global.set_initializer(&basic_type.const_zero());
+                let init_val = var_decl.initializer.generate_code(self).unwrap();
+                self.builder.build_store(global.as_pointer_value(), init_val).unwrap();
+            }
+            _ => statement.generate_code(self),
+        }
+        if !self.current_block_terminated() {
+            self.builder.build_return(None).unwrap();
+        }
+    }
+
+    fn declare_runtime(&self) {
+        self.declare_printf();
+        self.declare_scanf();
+        self.declare_snprintf();
+        self.declare_malloc();
+        self.declare_strcmp();
+        self.declare_pow();
+        self.declare_floor();
+        self.declare_fflush();
+        if self.profile {
+            self.declare_atexit();
+        }
+        if self.max_loop_iterations.is_some() {
+            self.declare_exit();
+        }
+    }
+
+    // Generates code for another architecture, e.g. `aarch64-unknown-linux-gnu`.
+    // The module gets the triple and that machine's data layout.
+    pub fn set_target(&mut self, triple: &str) -> Result<(), String> {
+        let machine = target_machine(Some(triple), OptimizationLevel::Default)?;
+        self.module.set_triple(&machine.get_triple());
+        self.module.set_data_layout(&machine.get_target_data().get_data_layout());
+        self.target = Some(triple.to_string());
+        Ok(())
+    }
+
+    // Runs LLVM passes over the generated module according to the `-O` level:
+    //   0 - nothing, the IR is written exactly as generated
+    //   1 - mem2reg (promotes the allocas emitted for every variable to registers),
+    //       instcombine and dce
+    //   2 - level 1 plus gvn to remove redundant loads and computations
+    //   3 - level 2 plus simplifycfg and aggressive dead code elimination (adce)
+    pub fn optimize(&self, level: u8) -> Result<(), String> {
+        let passes = match level {
+            0 => return Ok(()),
+            1 => "mem2reg,instcombine,dce",
+            2 => "mem2reg,instcombine,gvn,dce",
+            _ => "mem2reg,instcombine,gvn,simplifycfg,adce",
+        };
+
+        let machine = target_machine(self.target.as_deref(), OptimizationLevel::Default)?;
+        self.module
+            .run_passes(passes, &machine, PassBuilderOptions::create())
+            .map_err(|e| e.to_string())
+    }
+
     pub fn write_to_file(&self, file_name: &str) {
         std::fs::write(file_name, self.module.print_to_string().to_string()).expect("Unable to write file");
     }
 
+    pub fn write_object_file(&self, file_name: &str, machine: &TargetMachine) -> Result<(), String> {
+        machine
+            .write_to_file(&self.module, FileType::Object, Path::new(file_name))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn write_assembly_file(&self, file_name: &str, machine: &TargetMachine) -> Result<(), String> {
+        machine
+            .write_to_file(&self.module, FileType::Assembly, Path::new(file_name))
+            .map_err(|e| e.to_string())
+    }
+
+    // Whether the block being generated already ended with a return, branch or unreachable.
+    // Nothing may be appended to such a block.
+    pub fn current_block_terminated(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .and_then(|block| block.get_terminator())
+            .is_some()
+    }
+
     pub fn void_type(&self) -> VoidType<'ctx> {
         self.llvm_context.void_type()
     }
@@ -52,14 +165,200 @@ impl<'ctx> CodeGenerator<'ctx> {
             TypeNode::NumberType => self.llvm_context.f64_type().as_basic_type_enum(),
             TypeNode::BooleanType => self.llvm_context.bool_type().as_basic_type_enum(),
             TypeNode::StringType => self.llvm_context.ptr_type(inkwell::AddressSpace::default()).as_basic_type_enum(),
+            TypeNode::ArrayType(element_type, length) => self.to_basic_type(element_type).array_type(*length as u32).as_basic_type_enum(),
+            // Procedures yielding several values return them in a struct.
+            TypeNode::TupleType(element_types) => {
+                let field_types: Vec<BasicTypeEnum> = element_types.iter().map(|t| self.to_basic_type(t)).collect();
+                self.llvm_context.struct_type(&field_types, false).as_basic_type_enum()
+            }
             _ => panic!("to_basic_type called for void type, call void_type instead"),
         }
     }
 
+    // C promotes variadic arguments narrower than an int, so booleans (`i1`) have to be widened
+    // before they are handed to printf. Otherwise the callee reads whatever else is in the register.
+    pub fn promote_variadic_arg(&self, value: BasicValueEnum<'ctx>) -> BasicValueEnum<'ctx> {
+        match value {
+            BasicValueEnum::IntValue(int_value) if int_value.get_type().get_bit_width() == 1 => self.builder
+                .build_int_z_extend(int_value, self.llvm_context.i32_type(), "promotetmp")
+                .unwrap()
+                .into(),
+            _ => value,
+        }
+    }
+
     fn declare_printf(&self) -> FunctionValue<'ctx> {
         let i8_ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
         let printf_type = self.llvm_context.i32_type().fn_type(&[i8_ptr_type.into()], true);
         self.module.add_function("printf", printf_type, None)
     }
+
+    fn declare_scanf(&self) -> FunctionValue<'ctx> {
+        let i8_ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let scanf_type = self.llvm_context.i32_type().fn_type(&[i8_ptr_type.into()], true);
+        self.module.add_function("scanf", scanf_type, None)
+    }
+
+    fn declare_snprintf(&self) -> FunctionValue<'ctx> {
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let snprintf_type = self.llvm_context.i32_type().fn_type(&[ptr_type.into(), self.llvm_context.i64_type().into(), ptr_type.into()], true);
+        self.module.add_function("snprintf", snprintf_type, None)
+    }
+
+    fn declare_malloc(&self) -> FunctionValue<'ctx> {
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let malloc_type = ptr_type.fn_type(&[self.llvm_context.i64_type().into()], false);
+        self.module.add_function("malloc", malloc_type, None)
+    }
+
+    fn declare_strcmp(&self) -> FunctionValue<'ctx> {
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let strcmp_type = self.llvm_context.i32_type().fn_type(&[ptr_type.into(), ptr_type.into()], false);
+        self.module.add_function("strcmp", strcmp_type, None)
+    }
+
+    fn declare_pow(&self) -> FunctionValue<'ctx> {
+        let f64_type = self.llvm_context.f64_type();
+        let pow_type = f64_type.fn_type(&[f64_type.into(), f64_type.into()], false);
+        self.module.add_function("llvm.pow.f64", pow_type, None)
+    }
+
+    fn declare_floor(&self) -> FunctionValue<'ctx> {
+        let f64_type = self.llvm_context.f64_type();
+        let floor_type = f64_type.fn_type(&[f64_type.into()], false);
+        self.module.add_function("llvm.floor.f64", floor_type, None)
+    }
+
+    // Declares `fflush` along with libc's `stdout` stream it is called on.
+    fn declare_fflush(&self) -> FunctionValue<'ctx> {
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        self.module.add_global(ptr_type, None, "stdout");
+        let fflush_type = self.llvm_context.i32_type().fn_type(&[ptr_type.into()], false);
+        self.module.add_function("fflush", fflush_type, None)
+    }
+
+    pub fn flush_stdout(&self) {
+        let fflush_function = self.module.get_function("fflush").expect("fflush function not declared");
+        let stdout_global = self.module.get_global("stdout").expect("stdout global not declared");
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let stdout = self.builder.build_load(ptr_type, stdout_global.as_pointer_value(), "stdout").unwrap();
+        self.builder.build_call(fflush_function, &[stdout.into()], "flushtmp").unwrap();
+    }
+
+    fn declare_atexit(&self) -> FunctionValue<'ctx> {
+        let ptr_type = self.llvm_context.ptr_type(inkwell::AddressSpace::default());
+        let atexit_type = self.llvm_context.i32_type().fn_type(&[ptr_type.into()], false);
+        self.module.add_function("atexit", atexit_type, None)
+    }
+
+    fn declare_exit(&self) -> FunctionValue<'ctx> {
+        let exit_type = self.void_type().fn_type(&[self.llvm_context.i32_type().into()], false);
+        self.module.add_function("exit", exit_type, None)
+    }
+
+    // Allocates in the entry block of the current function, so the slot isn't re-allocated
+    // every time a nested loop is entered.
+    pub fn build_entry_alloca<T: BasicType<'ctx>>(&self, var_type: T, name: &str) -> PointerValue<'ctx> {
+        let entry_block = self.function.unwrap().get_first_basic_block().unwrap();
+        let current_block = self.builder.get_insert_block().unwrap();
+        match entry_block.get_first_instruction() {
+            Some(instruction) => self.builder.position_before(&instruction),
+            None => self.builder.position_at_end(entry_block),
+        }
+        let alloca = self.builder.build_alloca(var_type, name).unwrap();
+        self.builder.position_at_end(current_block);
+        alloca
+    }
+
+    // Counts an iteration of the loop owning `counter` and exits the program once it has run
+    // more than `--max-loop-iterations` times.
+    pub fn check_loop_iterations(&self, counter: PointerValue<'ctx>, limit: u64) {
+        let i64_type = self.llvm_context.i64_type();
+        let function = self.function.unwrap();
+        let printf_function = self.module.get_function("printf").expect("printf function not declared");
+        let exit_function = self.module.get_function("exit").expect("exit function not declared");
+
+        let current = self.builder.build_load(i64_type, counter, "itercount").unwrap().into_int_value();
+        let incremented = self.builder.build_int_add(current, i64_type.const_int(1, false), "itercountinc").unwrap();
+        self.builder.build_store(counter, incremented).unwrap();
+        let exceeded = self.builder.build_int_compare(inkwell::IntPredicate::UGT, incremented, i64_type.const_int(limit, false), "iterexceeded").unwrap();
+
+        let limit_bb = self.llvm_context.append_basic_block(function, "looplimit");
+        let body_bb = self.llvm_context.append_basic_block(function, "loopbody");
+        self.builder.build_conditional_branch(exceeded, limit_bb, body_bb).unwrap();
+
+        self.builder.position_at_end(limit_bb);
+        let message = self.builder.build_global_string_ptr(&format!("Loop exceeded {} iterations, aborting\n", limit), "looplimit_message").unwrap();
+        self.builder.build_call(printf_function, &[message.as_pointer_value().into()], "printtmp").unwrap();
+        self.builder.build_call(exit_function, &[self.llvm_context.i32_type().const_int(1, false).into()], "").unwrap();
+        self.builder.build_unreachable().unwrap();
+
+        self.builder.position_at_end(body_bb);
+    }
+
+    pub fn declare_call_counter(&mut self, procedure_name: &str) -> GlobalValue<'ctx> {
+        let i64_type = self.llvm_context.i64_type();
+        let counter = self.module.add_global(i64_type, None, &format!("__ziget_calls_{}", procedure_name));
+        counter.set_initializer(&i64_type.const_zero());
+        self.call_counters.push((procedure_name.to_string(), counter));
+        counter
+    }
+
+    pub fn increment_call_counter(&self, counter: GlobalValue<'ctx>) {
+        let i64_type = self.llvm_context.i64_type();
+        let current = self.builder.build_load(i64_type, counter.as_pointer_value(), "callcount").unwrap().into_int_value();
+        let incremented = self.builder.build_int_add(current, i64_type.const_int(1, false), "callcountinc").unwrap();
+        self.builder.build_store(counter.as_pointer_value(), incremented).unwrap();
+    }
+
+    // Builds a function printing every call counter and registers it with atexit,
+    // so the summary is printed however the program terminates.
+    pub fn register_profile_dump(&self) {
+        let i64_type = self.llvm_context.i64_type();
+        let printf_function = self.module.get_function("printf").expect("printf function not declared");
+        let atexit_function = self.module.get_function("atexit").expect("atexit function not declared");
+
+        let return_block = self.builder.get_insert_block();
+
+        let dump_function = self.module.add_function("__ziget_profile_dump", self.void_type().fn_type(&[], false), None);
+        let entry_block = self.llvm_context.append_basic_block(dump_function, "entry");
+        self.builder.position_at_end(entry_block);
+
+        let header = self.builder.build_global_string_ptr("Procedure call counts:\n", "profile_header").unwrap();
+        self.builder.build_call(printf_function, &[header.as_pointer_value().into()], "printtmp").unwrap();
+
+        for (name, counter) in &self.call_counters {
+            let format_string = self.builder.build_global_string_ptr(&format!("  {}: %ld\n", name), "profile_format").unwrap();
+            let count = self.builder.build_load(i64_type, counter.as_pointer_value(), "callcount").unwrap();
+            self.builder.build_call(printf_function, &[format_string.as_pointer_value().into(), count.into()], "printtmp").unwrap();
+        }
+        self.builder.build_return(None).unwrap();
+
+        if let Some(block) = return_block {
+            self.builder.position_at_end(block);
+        }
+        self.builder.build_call(atexit_function, &[dump_function.as_global_value().as_pointer_value().into()], "atexittmp").unwrap();
+    }
 }
 
+// Creates a machine for the given triple, or for the host (tuned to its CPU) when there is none.
+pub fn target_machine(triple: Option<&str>, level: OptimizationLevel) -> Result<TargetMachine, String> {
+    let (triple, cpu, features) = match triple {
+        Some(triple) => {
+            Target::initialize_all(&InitializationConfig::default());
+            (TargetTriple::create(triple), "generic".to_string(), String::new())
+        }
+        None => {
+            Target::initialize_native(&InitializationConfig::default())?;
+            (
+                TargetMachine::get_default_triple(),
+                TargetMachine::get_host_cpu_name().to_string(),
+                TargetMachine::get_host_cpu_features().to_string(),
+            )
+        }
+    };
+    let target = Target::from_triple(&triple).map_err(|e| e.to_string())?;
+    target
+        .create_target_machine(&triple, &cpu, &features, level, RelocMode::PIC, CodeModel::Default)
+        .ok_or_else(|| format!("Could not create a target machine for {}", triple.as_str().to_string_lossy()))
+}
@@ -13,6 +13,8 @@ where
     buffer: String,
     current_line: usize,
     current_column: usize,
+    token_line: usize,
+    token_column: usize,
     last_char: Option<char>,
 }
 
@@ -20,8 +22,52 @@ impl<I> Lexer<I>
 where
     I: Iterator<Item = char>,
 {
-    fn create_token(&self, token_type: TokenType) -> Token {
-        Token::new(token_type, self.buffer.clone(), self.current_line, self.current_column - self.buffer.len())
+    // Moves the buffer into the token instead of copying it, leaving an empty buffer for the next one.
+    fn create_token(&mut self, token_type: TokenType) -> Token {
+        Token::new(token_type, std::mem::take(&mut self.buffer), self.token_line, self.token_column)
+    }
+
+    // Pulls the next character from the input, keeping `current_line` and `current_column`
+    // pointing at the character that was just read (columns are 1-based).
+    // A `\r\n` pair is handed out as a single `\n`, so CRLF sources lex exactly like LF ones.
+    fn next_char(&mut self) -> Option<char> {
+        let mut c = self.input.next()?;
+        if c == '\r' && self.input.peek() == Some(&'\n') {
+            c = self.input.next()?;
+        }
+        if c == '\n' {
+            self.current_line += 1;
+            self.current_column = 0;
+        } else {
+            self.current_column += 1;
+        }
+        Some(c)
+    }
+
+    // Appends to the token buffer, remembering where the token started on its first character.
+    fn push_char(&mut self, c: char) {
+        if self.buffer.is_empty() {
+            self.token_line = self.current_line;
+            self.token_column = self.current_column;
+        }
+        self.buffer.push(c);
+    }
+
+    // Consumes a `#{ ... }#` block comment after its opening `#{` has been read.
+    // Returns an invalid token pointing at the opening `#{` if the input ends first.
+    fn skip_block_comment(&mut self) -> Option<Token> {
+        let start_line = self.current_line;
+        let start_column = self.current_column - 1;
+        let mut previous = None;
+
+        while let Some(c) = self.next_char() {
+            if previous == Some('}') && c == '#' {
+                return None;
+            }
+            previous = Some(c);
+        }
+
+        Some(Token::new(TokenType::Invalid, "#{".to_string(), start_line, start_column))
     }
 
     pub fn new(input: I) -> Self {
@@ -31,6 +77,8 @@ where
             buffer: String::new(),
             current_line: 1,
             current_column: 0,
+            token_line: 1,
+            token_column: 1,
             last_char: None
         }
     }
@@ -45,41 +93,35 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         self.current_state = State::Start;
         self.buffer.clear();
-        while self.input.peek() != None {
+        while self.last_char.is_some() || self.input.peek().is_some() {
+            // A character handed back through `last_char` has already been counted by `next_char`.
             let c = if let Some(ch) = self.last_char { 
                 self.last_char = None; 
                 ch 
             } else { 
-                if let Some(ch) = self.input.next() { ch } else { break; }
+                if let Some(ch) = self.next_char() { ch } else { break; }
             };
+            let previous_state = self.current_state;
             self.current_state = self.current_state.transition(c);
             if self.current_state == State::Start && self.buffer.is_empty() {
-                if c == '\n' {
-                    self.current_line += 1;
-                    self.current_column = 0;
-                }
                 self.current_state = self.current_state.transition(c);
             }
-            if c == '\n' {
-                self.current_line += 1;
-                self.current_column = 0;
-            }
-            self.current_column += 1;
             match self.current_state {
-                State::Identifier => self.buffer.push(c),
-                State::Number => self.buffer.push(c),
-                State::String => self.buffer.push(c),
-                State::Dot => self.buffer.push(c),
+                State::Identifier => self.push_char(c),
+                State::Number => self.push_char(c),
+                State::NumberSeparator => self.push_char(c),
+                State::String => self.push_char(c),
+                State::StringEscape => self.push_char(c),
+                State::Dot => self.push_char(c),
                 State::MinusOrArrow => {
-                    self.buffer.push(c);
+                    self.push_char(c);
                     if self.input.peek().is_none() {
                         return Some(self.create_token(TokenType::Operator(Operator::Minus)));
                     }
                     let token_type =  match self.input.peek().unwrap_or(&' ') {
                         '>' => {
-                            self.buffer.push('>');
-                            self.input.next();
-                            self.current_column += 1;
+                            self.push_char('>');
+                            self.next_char();
                             TokenType::Operator(Operator::Arrow)
                         }
                         _ => TokenType::Operator(Operator::Minus),
@@ -87,15 +129,14 @@ where
                     return Some(self.create_token(token_type));
                 },
                 State::GtOrGe => {
-                    self.buffer.push(c);
+                    self.push_char(c);
                     if self.input.peek().is_none() {
                         return Some(self.create_token(TokenType::Operator(Operator::Gt)));
                     }
                     let token_type =  match self.input.peek().unwrap_or(&' ') {
                         '=' => {
-                            self.buffer.push('=');
-                            self.input.next();
-                            self.current_column += 1;
+                            self.push_char('=');
+                            self.next_char();
                             TokenType::Operator(Operator::Gte)
                         }
                         _ => TokenType::Operator(Operator::Gt),
@@ -103,15 +144,14 @@ where
                     return Some(self.create_token(token_type));
                 },
                 State::LtOrLe => {
-                    self.buffer.push(c);
+                    self.push_char(c);
                     if self.input.peek().is_none() {
                         return Some(self.create_token(TokenType::Operator(Operator::Lt)));
                     }
                     let token_type =  match self.input.peek().unwrap() {
                         '=' => {
-                            self.buffer.push('=');
-                            self.input.next();
-                            self.current_column += 1;
+                            self.push_char('=');
+                            self.next_char();
                             TokenType::Operator(Operator::Lte)
                         }
                         _ => TokenType::Operator(Operator::Lt),
@@ -119,26 +159,33 @@ where
                     return Some(self.create_token(token_type));
                 },
                 State::AssignOrError => {
-                    self.buffer.push(c);
-                    if self.input.peek().is_none() {
-                        return Some(self.create_token(TokenType::Invalid));
-                    }
-
-                    let next_char = self.input.next().unwrap_or(' ');
-                    self.buffer.push(next_char);
-                    let token_type =  match next_char {
-                        '=' => TokenType::Operator(Operator::Assign),
+                    self.push_char(c);
+                    // A lone `:` becomes an invalid token of its own, leaving whatever follows it to be lexed normally.
+                    let token_type =  match self.input.peek() {
+                        Some('=') => {
+                            self.push_char('=');
+                            self.next_char();
+                            TokenType::Operator(Operator::Assign)
+                        }
                         _ => TokenType::Invalid,
                     };
                     return Some(self.create_token(token_type));
                 },
                 State::Operator => {
-                    self.buffer.push(c);
+                    self.push_char(c);
                     let token_type =  match c {
                         '+' => Some(TokenType::Operator(Operator::Plus)),
                         '*' => Some(TokenType::Operator(Operator::Times)),
-                        '/' => Some(TokenType::Operator(Operator::Over)),
+                        '/' => match self.input.peek() {
+                            Some('/') => {
+                                self.push_char('/');
+                                self.next_char();
+                                Some(TokenType::Operator(Operator::FloorOver))
+                            }
+                            _ => Some(TokenType::Operator(Operator::Over)),
+                        },
                         '%' => Some(TokenType::Operator(Operator::Mod)),
+                        '^' => Some(TokenType::Operator(Operator::Power)),
                         _ => Some(TokenType::Invalid)
                     };
                     if let Some(t) = token_type {
@@ -146,10 +193,12 @@ where
                     }
                 },
                 State::Delimiter => {
-                    self.buffer.push(c);
+                    self.push_char(c);
                     let token_type =  match c {
                         '{' => Some(TokenType::Delimiter(Delimiter::LeftBrace)),
                         '}' => Some(TokenType::Delimiter(Delimiter::RightBrace)),
+                        '[' => Some(TokenType::Delimiter(Delimiter::LeftBracket)),
+                        ']' => Some(TokenType::Delimiter(Delimiter::RightBracket)),
                         '(' => Some(TokenType::Delimiter(Delimiter::LeftParenthesis)),
                         ')' => Some(TokenType::Delimiter(Delimiter::RightParenthesis)),
                         ',' => Some(TokenType::Delimiter(Delimiter::Comma)),
@@ -169,26 +218,38 @@ where
                         };
                         return Some(self.create_token(token_type));
                     }
-                    while let Some(c) = self.input.next() {
+                    if self.input.peek() == Some(&'{') {
+                        let brace = self.next_char().unwrap();
+                        self.current_state = self.current_state.transition(brace);
+                        if let Some(invalid_token) = self.skip_block_comment() {
+                            return Some(invalid_token);
+                        }
+                        self.current_state = State::Start;
+                        continue;
+                    }
+                    while let Some(c) = self.next_char() {
                         if c == '\n' {
-                            self.current_line += 1;
-                            self.current_column = 0;
                             break;
                         }
-                        self.current_column += 1;
-                    } 
+                    }
                     self.current_state = State::Start;
                 }
+                State::BlockComment => (),
                 State::Whitespace => (),
                 State::Invalid => {
-                    self.buffer.push(c);
+                    // A trailing `_` ends the number as an invalid token, and whatever follows it is lexed on its own.
+                    if previous_state == State::NumberSeparator {
+                        self.last_char = Some(c);
+                    } else {
+                        self.push_char(c);
+                    }
                     return Some(self.create_token(TokenType::Invalid));
                 }
                 State::Start => {
                     if !self.buffer.is_empty() {
                         if c == '"' {
                             self.last_char = None;
-                            self.buffer.push('"');
+                            self.push_char('"');
                         } else {
                             self.last_char = Some(c);
                         }
@@ -198,11 +259,14 @@ where
                         };
                         return Some(self.create_token(token_type));
                     } else {
-                        self.buffer.push(c);
+                        self.push_char(c);
                     }
                 }
             }
         }
+        if matches!(self.current_state, State::String | State::StringEscape) {
+            return Some(self.create_token(TokenType::Invalid));
+        }
         if !self.buffer.is_empty() {
             let token_type = match TokenType::from_str(&self.buffer) {
                 Ok(tt) => tt,
@@ -3,7 +3,9 @@ pub enum State {
     Start,
     Identifier,
     Number,
+    NumberSeparator,
     String,
+    StringEscape,
     Operator,
     Delimiter,
     Dot,
@@ -15,6 +17,7 @@ pub enum State {
     AssignOrError,
 
     Comment,
+    BlockComment,
 
     Invalid,
 }
@@ -25,13 +28,13 @@ impl State {
             (State::Start, 'a'..='z' | 'A'..='Z') => State::Identifier,
             (State::Start, '0'..='9') => State::Number,
             (State::Start, '"') => State::String,
-            (State::Start, '+' | '/' | '*' | '%') => State::Operator,
+            (State::Start, '+' | '/' | '*' | '%' | '^') => State::Operator,
             (State::Start, '#') => State::Comment,
             (State::Start, '-') => State::MinusOrArrow,
             (State::Start, '<') => State::LtOrLe,
             (State::Start, '>') => State::GtOrGe,
             (State::Start, ':') => State::AssignOrError,
-            (State::Start, '(' | ')' | '{' | '}' | ',' | ';') => State::Delimiter,
+            (State::Start, '(' | ')' | '{' | '}' | '[' | ']' | ',' | ';') => State::Delimiter,
             (State::Start, ' ' | '\t' | '\n' | '\r') => State::Whitespace,
             (State::Start, _) => State::Invalid,
 
@@ -40,14 +43,22 @@ impl State {
 
             (State::Number, '0'..='9') => State::Number,
             (State::Number, '.') => State::Dot,
+            (State::Number, '_') => State::NumberSeparator,
             (State::Number, _) => State::Start,
 
+            (State::NumberSeparator, '0'..='9') => State::Number,
+            (State::NumberSeparator, '_') => State::NumberSeparator,
+            (State::NumberSeparator, _) => State::Invalid,
+
             (State::String, '"') => State::Start,
+            (State::String, '\\') => State::StringEscape,
             (State::String, _) => State::String,
 
+            (State::StringEscape, _) => State::String,
+
             (State::Operator, _) => State::Start,
 
-            (State::Delimiter, '(' | ')' | '{' | '}' | ',' | ';') => State::Delimiter,
+            (State::Delimiter, '(' | ')' | '{' | '}' | '[' | ']' | ',' | ';') => State::Delimiter,
             (State::Delimiter, _) => State::Invalid,
 
             (State::Dot, '0'..='9') => State::Number,
@@ -61,7 +72,9 @@ impl State {
             (State::MinusOrArrow, _) => State::Operator,
             (State::AssignOrError, _) => State::Invalid,
             (State::Comment, '\n') => State::Start,
+            (State::Comment, '{') => State::BlockComment,
             (State::Comment, _) => State::Comment,
+            (State::BlockComment, _) => State::BlockComment,
 
             (State::Invalid, _) => State::Invalid,
         }
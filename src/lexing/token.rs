@@ -22,12 +22,15 @@ pub enum Keyword {
     When,      // `when`
     Otherwise, // `otherwise`
     Loop,      // `loop`
+    While,     // `while`
     Yield,     // `yield`
     NumType,   // `number`
     BoolType,  // `boolean`
     StrType,   // `string`
     Leave,     // `break`
     Repeat,     // `repeat`
+    NoReturn,  // `noreturn`
+    Export,    // `export`
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -36,7 +39,9 @@ pub enum Operator {
     Minus,       // -
     Times,       // *
     Over,        // /
+    FloorOver,   // //
     Mod,         // %
+    Power,       // ^
     Lt,          // <
     Gt,          // >
     Lte,         // <=
@@ -47,6 +52,7 @@ pub enum Operator {
     Isnt,        // `isnt`
     And,         // `and`
     Or,          // `or`
+    Not,         // `not`
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -55,6 +61,8 @@ pub enum Delimiter {
     RightParenthesis, // )
     LeftBrace,        // {
     RightBrace,       // }
+    LeftBracket,      // [
+    RightBracket,     // ]
     Comma,            // ,
     StatementEnd,     // ;
 }
@@ -76,6 +84,41 @@ impl Token {
             column,
         }
     }
+
+    // Explains why an invalid token was rejected when the lexeme alone doesn't make it obvious.
+    pub fn invalid_reason(&self) -> Option<String> {
+        if self.token_type != TokenType::Invalid {
+            return None;
+        }
+        if self.lexeme.starts_with('"') && !is_terminated_string(&self.lexeme) {
+            Some(format!("unterminated string literal starting at line {}", self.line))
+        } else if self.lexeme.starts_with('"') {
+            decode_escapes(&self.lexeme[1..self.lexeme.len() - 1]).err()
+        } else if self.lexeme == "#{" {
+            Some(format!("unterminated block comment starting at line {}", self.line))
+        } else if self.lexeme == ":" {
+            Some("':' on its own is not valid, use ':=' for assignment".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+// One token per line in the tokens file, e.g. `3:5 <KEYWORD> 'define'`.
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match &self.token_type {
+            TokenType::Identifier(_) => "IDENTIFIER",
+            TokenType::Keyword(_) => "KEYWORD",
+            TokenType::Literal(Literal::NumberLiteral(_)) => "NUMBER",
+            TokenType::Literal(Literal::StringLiteral(_)) => "STRING",
+            TokenType::Literal(Literal::BooleanLiteral(_)) => "BOOLEAN",
+            TokenType::Operator(_) => "OPERATOR",
+            TokenType::Delimiter(_) => "DELIMITER",
+            TokenType::Invalid => "INVALID",
+        };
+        write!(f, "{}:{} <{}> '{}'", self.line, self.column, kind, self.lexeme)
+    }
 }
 
 impl std::str::FromStr for TokenType {
@@ -84,9 +127,13 @@ impl std::str::FromStr for TokenType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(n) = s.parse::<f64>() {
             Ok(TokenType::Literal(Literal::NumberLiteral(n)))
-        } else if s.starts_with('"') && s.ends_with('"') {
-
-            Ok(TokenType::Literal(Literal::StringLiteral(s[1..s.len() - 1].to_string())))
+        } else if s.starts_with(|c: char| c.is_ascii_digit()) {
+            strip_digit_separators(s)
+                .and_then(|digits| digits.parse::<f64>().ok())
+                .map(|n| TokenType::Literal(Literal::NumberLiteral(n)))
+                .ok_or(format!("Malformed number literal '{}'", s))
+        } else if s.starts_with('"') && is_terminated_string(s) {
+            decode_escapes(&s[1..s.len() - 1]).map(|text| TokenType::Literal(Literal::StringLiteral(text)))
         } else if let Some(boolean) = match s {
             "yes" => Some(Literal::BooleanLiteral(true)),
             "no" => Some(Literal::BooleanLiteral(false)),
@@ -96,6 +143,8 @@ impl std::str::FromStr for TokenType {
         } else if let Some(operator) = match s {
             "and" => Some(Operator::And),
             "or" => Some(Operator::Or),
+            "not" => Some(Operator::Not),
+            "^" => Some(Operator::Power),
             "is" => Some(Operator::Is),
             "isnt" => Some(Operator::Isnt),
             _ => None,
@@ -107,8 +156,11 @@ impl std::str::FromStr for TokenType {
             "when" => Some(Keyword::When),
             "otherwise" => Some(Keyword::Otherwise),
             "loop" => Some(Keyword::Loop),
+            "while" => Some(Keyword::While),
             "leave" => Some(Keyword::Leave),
             "repeat" => Some(Keyword::Repeat),
+            "noreturn" => Some(Keyword::NoReturn),
+            "export" => Some(Keyword::Export),
             "yield" => Some(Keyword::Yield),
             "number" => Some(Keyword::NumType),
             "boolean" => Some(Keyword::BoolType),
@@ -123,6 +175,21 @@ impl std::str::FromStr for TokenType {
 }
 
 
+// Removes `_` separators from a number literal, as long as every one of them sits between two digits.
+fn strip_digit_separators(s: &str) -> Option<String> {
+    let chars: Vec<char> = s.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c != '_' {
+            continue;
+        }
+        let digit_before = i > 0 && chars[i - 1].is_ascii_digit();
+        let digit_after = chars.get(i + 1).map_or(false, |c| c.is_ascii_digit());
+        if !digit_before || !digit_after {
+            return None;
+        }
+    }
+    Some(s.replace('_', ""))
+}
 
 impl Operator {
     pub fn get_precedence(&self) -> u8 {
@@ -142,10 +209,88 @@ impl Operator {
             Operator::Minus => 5,
             Operator::Times => 6,
             Operator::Over => 6,
+            Operator::FloorOver => 6,
             Operator::Mod => 6,
+            Operator::Power => 7,
 
+            Operator::Not => 0,
             Operator::Assign => 0,
             Operator::Arrow => 0,
         }
     }
 }
+
+// Whether a lexeme starting with `"` also ends with a closing `"` that isn't escaped.
+fn is_terminated_string(lexeme: &str) -> bool {
+    let mut chars = lexeme.chars().skip(1);
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '"' => return chars.next().is_none(),
+            _ => (),
+        }
+    }
+    false
+}
+
+// Replaces the escape sequences in the text of a string literal with the characters they stand for:
+//   \n \t \r \\ \" - the usual control characters, backslash and quote
+//   \xNN           - an ASCII character given by two hex digits, from \x01 up to \x7F
+//   \u{NNNN}       - any unicode character given by one to six hex digits
+fn decode_escapes(text: &str) -> Result<String, String> {
+    let mut decoded = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => decoded.push('\n'),
+            Some('t') => decoded.push('\t'),
+            Some('r') => decoded.push('\r'),
+            Some('\\') => decoded.push('\\'),
+            Some('"') => decoded.push('"'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(format!("invalid escape '\\x{}', expected two hex digits", digits));
+                }
+                let value = u8::from_str_radix(&digits, 16).unwrap();
+                // A NUL would silently end the C string the literal becomes.
+                if value == 0 {
+                    return Err("escape '\\x00' is not allowed, strings cannot contain NUL characters".to_string());
+                }
+                if value > 0x7f {
+                    return Err(format!("escape '\\x{}' is out of range, use '\\u{{...}}' for characters past \\x7F", digits));
+                }
+                decoded.push(value as char);
+            }
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err("invalid escape '\\u', expected '\\u{...}'".to_string());
+                }
+                let mut digits = String::new();
+                let mut closed = false;
+                for d in chars.by_ref() {
+                    if d == '}' {
+                        closed = true;
+                        break;
+                    }
+                    digits.push(d);
+                }
+                if !closed || digits.is_empty() || digits.len() > 6 || !digits.chars().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(format!("invalid escape '\\u{{{}', expected one to six hex digits", digits));
+                }
+                match char::from_u32(u32::from_str_radix(&digits, 16).unwrap()) {
+                    Some('\0') => return Err(format!("escape '\\u{{{}}}' is not allowed, strings cannot contain NUL characters", digits)),
+                    Some(character) => decoded.push(character),
+                    None => return Err(format!("escape '\\u{{{}}}' is not a valid unicode character", digits)),
+                }
+            }
+            Some(other) => return Err(format!("unknown escape sequence '\\{}'", other)),
+            None => return Err("string literal ends with a lone '\\'".to_string()),
+        }
+    }
+    Ok(decoded)
+}
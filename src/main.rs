@@ -2,18 +2,20 @@ use std::{error::Error, path::Path};
 pub mod lexing;
 pub mod parsing;
 pub mod codegen;
+pub mod builtins;
 
 mod cmd;
 use clap::Parser;
-use cmd::{run, Config};
+use cmd::{eval, list_builtins, repl, run, Backend, Config, DumpIr, Emit, MessageFormat};
+use parsing::semantic_analyzer::ShadowingPolicy;
 
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Specify the input .zg file
-    #[arg()]
-    input_file: String,
+    /// Specify the input .zg file, or - to read the source from stdin
+    #[arg(required_unless_present_any = ["eval", "list_builtins", "repl"])]
+    input_file: Option<String>,
 
     /// Specify the output file
     #[arg(short, long, default_value_t = format!("a.out"))]
@@ -31,35 +33,223 @@ pub struct Args {
     #[arg(short, long, default_value_t = false)]
     pub symbol_output: bool,
 
+    /// Write the tokens to this file instead of <input>-tokens.txt (implies -l)
+    #[arg(long, value_name = "PATH")]
+    pub tokens_file: Option<String>,
+
+    /// Write the parse tree to this file instead of <input>-tree.txt (implies -p)
+    #[arg(long, value_name = "PATH")]
+    pub tree_file: Option<String>,
+
+    /// Write the symbol tables to this file instead of <input>-symbol_tables.txt (implies -s)
+    #[arg(long, value_name = "PATH")]
+    pub symbol_file: Option<String>,
+
+    /// Flag to instrument procedures with call counters and print a summary at exit
+    #[arg(long, default_value_t = false)]
+    pub profile: bool,
+
+    /// Evaluate a single expression and print its value instead of compiling a file
+    #[arg(long)]
+    pub eval: Option<String>,
+
+    /// Start an interactive session that runs one statement or expression per line
+    #[arg(long, default_value_t = false)]
+    pub repl: bool,
+
+    /// Build the executable with the given clang sanitizer enabled
+    #[arg(long, value_parser = ["address"])]
+    pub sanitize: Option<String>,
+
+    /// Flag to write the symbol tables as an indented scope tree instead of a flat list
+    #[arg(long, default_value_t = false)]
+    pub symbol_tree: bool,
+
+    /// Flag to write the parse tree in a compact, readable form instead of the full debug output
+    #[arg(long, default_value_t = false)]
+    pub pretty: bool,
+
+    /// List the builtin procedures and their signatures
+    #[arg(long, default_value_t = false)]
+    pub list_builtins: bool,
+
+    /// Stop after producing the given artifact
+    #[arg(long, value_enum, default_value_t = Emit::Exe)]
+    pub emit: Emit,
+
+    /// Run the program directly through the JIT instead of producing an executable
+    #[arg(long, default_value_t = false)]
+    pub run: bool,
+
+    /// Optimization level for the generated code
+    #[arg(short = 'O', default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub opt_level: u8,
+
+    /// Backend used to produce assembly and object code
+    #[arg(long, value_enum, default_value_t = Backend::Clang)]
+    pub backend: Backend,
+
+    /// Target triple to generate code for, e.g. aarch64-unknown-linux-gnu (defaults to the host)
+    #[arg(long)]
+    pub target: Option<String>,
+
+    /// Also write the IR from before and/or after the -O passes
+    #[arg(long, value_enum)]
+    pub dump_ir: Option<DumpIr>,
+
+    /// Flush stdout after every print
+    #[arg(long, default_value_t = false)]
+    pub auto_flush: bool,
+
+    /// Use the first procedure as the entry point when none is named main
+    #[arg(long, default_value_t = false)]
+    pub implicit_main: bool,
+
+    /// Abort the program when a single loop runs more than N iterations
+    #[arg(long, value_name = "N")]
+    pub max_loop_iterations: Option<u64>,
+
+    /// Only check the program for errors, without generating any code
+    #[arg(long, default_value_t = false)]
+    pub check: bool,
+
+    /// How to report errors and warnings found during semantic analysis
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
+
+    /// Name of the procedure the program starts in
+    #[arg(long, default_value_t = format!("main"))]
+    pub entry: String,
+
+    /// Name of the LLVM module, defaults to the input file name without its extension
+    #[arg(long)]
+    pub module_name: Option<String>,
+
+    /// Also print the generated IR to stdout
+    #[arg(long, default_value_t = false)]
+    pub dump_ir_to_stdout: bool,
+
+    /// Show the output of the clang steps even when they succeed
+    #[arg(long, default_value_t = false)]
+    pub verbose: bool,
+
+    /// What to do when a variable declaration hides a variable of an enclosing scope
+    #[arg(long, value_enum, default_value_t = ShadowingPolicy::Deny)]
+    pub shadowing: ShadowingPolicy,
+
+    /// Warn about procedures declaring more than N parameters
+    #[arg(long, value_name = "N", default_value_t = 8)]
+    pub max_params: usize,
+
+    /// File extension expected on the input file, without the dot
+    #[arg(long, default_value_t = format!("zg"))]
+    pub lang_ext: String,
+
+    /// Treat warnings about unused variables, parameters and procedures as errors
+    #[arg(long, default_value_t = false)]
+    pub werror_unused: bool,
+
+}
+
+// The settings taken straight from the flags, for `--eval` as well as for compiling a file.
+// The artifact names depend on the input, so the caller passes them in, and no debug files are written.
+fn config_from_args<'a>(args: &'a Args, input_file: &'a Path, ir_file: &'a str, exe_file: &'a str, module_name: &'a str) -> Config<'a> {
+    Config {
+        input_file,
+        tokens_file: None,
+        tree_file: None,
+        symbol_table_file: None,
+        ir_file,
+        exe_file,
+        profile: args.profile,
+        sanitize: args.sanitize.as_deref(),
+        symbol_tree: args.symbol_tree,
+        pretty: args.pretty,
+        emit: args.emit,
+        jit: args.run,
+        opt_level: args.opt_level,
+        backend: args.backend,
+        target: args.target.as_deref(),
+        dump_ir: args.dump_ir,
+        auto_flush: args.auto_flush,
+        implicit_main: args.implicit_main,
+        max_loop_iterations: args.max_loop_iterations,
+        check: args.check,
+        message_format: args.message_format,
+        entry: &args.entry,
+        module_name,
+        ir_to_stdout: args.dump_ir_to_stdout,
+        verbose: args.verbose,
+        shadowing: args.shadowing,
+        max_params: args.max_params,
+        werror_unused: args.werror_unused,
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    if !Path::new(&args.input_file).exists() {
+    if args.list_builtins {
+        list_builtins();
+        return Ok(());
+    }
+    if let Some(expression) = &args.eval {
+        return eval(expression, config_from_args(&args, Path::new("<eval>"), "eval.ll", "eval.out", "eval"));
+    }
+    if args.repl {
+        return repl();
+    }
+    let input_file = args.input_file.clone().unwrap();
+
+    if args.sanitize.is_some() && args.backend == Backend::Llvm {
+        return Err("--sanitize requires the clang backend".into());
+    }
+
+    if args.run && args.target.is_some() {
+        return Err("--run can only execute programs built for the host".into());
+    }
+
+    // The C runtime starts executables in `main`, other entry points are only for embedding.
+    if args.entry != "main" && args.emit == Emit::Exe && !args.run {
+        return Err("--entry other than main requires --run or an --emit stage before exe".into());
+    }
+
+    if input_file != "-" && !Path::new(&input_file).exists() {
         eprintln!("Error: input file does not exist");
         return Ok(());
     }
 
-    let tokens_file_name = format!("{}-tokens.txt", &args.input_file.trim_end_matches(".zg"));
-    let tree_file_name =  format!("{}-tree.txt", &args.input_file.trim_end_matches(".zg"));
-    let symbol_table_file_name =  format!("{}-symbol_tables.txt", &args.input_file.trim_end_matches(".zg"));
-    let ir_file_name = format!("{}.ll", &args.input_file.trim_end_matches(".zg"));
+    let lang_ext = args.lang_ext.trim_start_matches('.');
+    if input_file != "-" && Path::new(&input_file).extension().map_or(true, |ext| ext != lang_ext) {
+        eprintln!("Warning: input file '{}' doesn't have the .{} extension", input_file, lang_ext);
+    }
+
+    // Source piped through stdin has no file name to derive the artifact names from. Otherwise the
+    // artifacts go next to the input, named after it without its extension, whatever that is.
+    let base_path = Path::new(&input_file).with_extension("");
+    let base_name = if input_file == "-" { "stdin" } else { base_path.to_str().unwrap_or(&input_file) };
+
+    let tokens_file_name = args.tokens_file.clone().unwrap_or_else(|| format!("{}-tokens.txt", base_name));
+    let tree_file_name = args.tree_file.clone().unwrap_or_else(|| format!("{}-tree.txt", base_name));
+    let symbol_table_file_name = args.symbol_file.clone().unwrap_or_else(|| format!("{}-symbol_tables.txt", base_name));
+    let ir_file_name = format!("{}.ll", base_name);
+    let module_name = match &args.module_name {
+        Some(name) => name.clone(),
+        None => Path::new(base_name).file_name().unwrap_or_default().to_string_lossy().into_owned(),
+    };
 
 
     let exe_file_name = if args.output == "a.out" {
-        format!("{}.out", &args.input_file.trim_end_matches(".zg"))
+        format!("{}.out", base_name)
     } else {
         args.output.clone()
     };
 
     run(Config {
-        input_file: Path::new(&args.input_file),
-        tokens_file: if args.lexer_output { Some(&tokens_file_name) } else { None },
-        tree_file: if args.lexer_output { Some(&tree_file_name) } else { None },
-        symbol_table_file: if args.lexer_output { Some(&symbol_table_file_name) } else { None },
-        ir_file: &ir_file_name,
-        exe_file: &exe_file_name
+        tokens_file: if args.lexer_output || args.tokens_file.is_some() || args.emit == Emit::Tokens { Some(&tokens_file_name) } else { None },
+        tree_file: if args.parser_output || args.tree_file.is_some() || args.emit == Emit::Ast { Some(&tree_file_name) } else { None },
+        symbol_table_file: if args.symbol_output || args.symbol_file.is_some() { Some(&symbol_table_file_name) } else { None },
+        ..config_from_args(&args, Path::new(&input_file), &ir_file_name, &exe_file_name, &module_name)
     })?;
 
     Ok(())
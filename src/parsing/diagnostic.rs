@@ -0,0 +1,97 @@
+use std::fmt;
+
+use super::node::Span;
+
+/// An error or warning found during semantic analysis, along with where in the source it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub kind: DiagnosticKind,
+}
+
+/// What a diagnostic is about, so that `--werror-unused` can single out unused-symbol warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DiagnosticKind {
+    #[default]
+    General,
+    Unused,
+}
+
+impl Diagnostic {
+    // Shows the offending source line with a caret under the reported column, e.g.
+    //
+    //   Variable 'b' is not declared.
+    //    --> line 3, column 17
+    //     |
+    //   3 |     define a := b;
+    //     |                 ^
+    //
+    // Falls back to the plain message for nodes without a position in the source.
+    pub fn render(&self, source: &str) -> String {
+        match self.excerpt(source) {
+            Some(excerpt) => format!(
+                "{}\n{}--> line {}, column {}\n{}",
+                self.message, " ".repeat(self.span.line.to_string().len()), self.span.line, self.span.column, excerpt
+            ),
+            None => self.to_string(),
+        }
+    }
+
+    // The source line with the caret under it, without the message.
+    pub fn excerpt(&self, source: &str) -> Option<String> {
+        let Span { line, column } = self.span;
+        let source_line = line.checked_sub(1).and_then(|index| source.lines().nth(index))?;
+
+        let gutter = " ".repeat(line.to_string().len());
+        // Tabs are kept so the caret lines up however wide the terminal renders them
+        let padding: String = source_line
+            .chars()
+            .take(column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        Some(format!("{} |\n{} | {}\n{} | {}^", gutter, line, source_line, gutter, padding))
+    }
+
+    // A single line in the `file:line:column: kind: message` form gcc and `rustc --error-format short` use, which editors
+    // and other tools can match on. Diagnostics without a position leave out the location.
+    pub fn to_line(&self, file: &str, kind: &str) -> String {
+        if self.span.line == 0 {
+            format!("{}: {}", kind, self.message)
+        } else {
+            format!("{}:{}:{}: {}: {}", file, self.span.line, self.span.column, kind, self.message)
+        }
+    }
+
+    // One line of JSON for `--message-format json`, where `kind` is "error" or "warning".
+    pub fn to_json(&self, kind: &str) -> String {
+        format!(
+            "{{\"kind\":\"{}\",\"message\":\"{}\",\"line\":{},\"column\":{}}}",
+            kind, escape_json(&self.message), self.span.line, self.span.column
+        )
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = self.message.trim_end_matches(['.', '!', '?']);
+        let ending = &self.message[text.len()..];
+        write!(f, "{} at line {}, column {}{}", text, self.span.line, self.span.column, ending)
+    }
+}
@@ -1,4 +1,5 @@
 pub mod node;
+pub mod diagnostic;
 pub mod symbol_table;
 pub mod parser;
 pub mod semantic_analyzer;
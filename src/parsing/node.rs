@@ -1,7 +1,19 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
 use crate::lexing::token::Operator;
 
+/// Where a node starts in the source, taken from its first token.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ProgramNode {
+    // Constants declared with `define` at the top level, visible to every procedure.
+    pub globals: Vec<VariableDeclarationNode>,
     pub procedures: Vec<ProcedureNode>,
     pub main: MainProcedureNode,
 }
@@ -12,11 +24,18 @@ pub struct ProcedureNode {
     pub params: Vec<ParameterNode>,
     pub return_type: TypeNode,
     pub body: BlockNode,
+    pub noreturn: bool,
+    pub export: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MainProcedureNode {
+    pub name: String,
+    // Either empty or a single number parameter receiving the count of command-line arguments.
+    pub params: Vec<ParameterNode>,
     pub body: BlockNode,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,13 +46,29 @@ pub struct BlockNode {
 #[derive(Debug, Clone, PartialEq)]
 pub enum StatementNode {
     VariableDeclaration(VariableDeclarationNode),
+    Destructure(DestructureNode),
     Assignment(AssignmentNode),
     Expression(ExpressionNode),
     Return(ReturnNode),
     Loop(LoopNode),
     Conditional(ConditionalNode),
-    Break,
-    Continue
+    Break(Span),
+    Continue(Span)
+}
+
+impl StatementNode {
+    pub fn span(&self) -> Span {
+        match self {
+            StatementNode::VariableDeclaration(var_decl) => var_decl.span,
+            StatementNode::Destructure(destructure) => destructure.span,
+            StatementNode::Assignment(assign) => assign.span,
+            StatementNode::Expression(expr) => expr.span(),
+            StatementNode::Return(ret) => ret.span,
+            StatementNode::Loop(loop_node) => loop_node.span,
+            StatementNode::Conditional(cond) => cond.span,
+            StatementNode::Break(span) | StatementNode::Continue(span) => *span,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -41,22 +76,54 @@ pub struct VariableDeclarationNode {
     pub name: String,
     pub var_type: Option<TypeNode>,
     pub initializer: ExpressionNode,
+    pub span: Span,
+}
+
+// `define a, b := f();` declares one variable for each value of the tuple `f` yields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestructureNode {
+    pub names: Vec<String>,
+    // Filled in by the analyzer from the type of the tuple.
+    pub var_types: Vec<TypeNode>,
+    pub value: ExpressionNode,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AssignmentNode {
-    pub name: String,
+    // More than one for a chained assignment like `a := b := 0`, which stores the value in each.
+    pub targets: Vec<String>,
     pub value: ExpressionNode,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ReturnNode {
     pub value: Option<ExpressionNode>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LoopNode {
+    pub condition: Option<ExpressionNode>,
+    // Number of iterations for `loop N times`
+    pub count: Option<ExpressionNode>,
+    // Counter and bounds for `loop i from A to B`
+    pub range: Option<LoopRange>,
     pub body: BlockNode,
+    // Runs when the loop finishes on its own, but not when it is exited with `leave`.
+    pub otherwise: Option<BlockNode>,
+    pub span: Span,
+}
+
+// `to` includes the upper bound and `until` stops right before it, so `from 0 to 3` runs four
+// times and `from 0 until 3` three.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopRange {
+    pub variable: String,
+    pub from: ExpressionNode,
+    pub to: ExpressionNode,
+    pub inclusive: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -64,6 +131,7 @@ pub struct ConditionalNode {
     pub condition: ExpressionNode,
     pub consequence: BlockNode,
     pub alternative: Option<BlockNode>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -71,8 +139,72 @@ pub enum ExpressionNode {
     BinaryOperation(Box<BinaryOperationNode>),
     UnaryOperation(Box<UnaryOperationNode>),
     Literal(LiteralNode),
-    Variable(String),
+    Variable(String, Span),
     ProcedureCall(ProcedureCallNode),
+    Cast(Box<CastNode>),
+    ArrayLiteral(Vec<ExpressionNode>, Span),
+    // The values of `yield a, b;`, the only place a tuple can be written.
+    Tuple(Vec<ExpressionNode>, Span),
+}
+
+impl ExpressionNode {
+    pub fn span(&self) -> Span {
+        match self {
+            ExpressionNode::BinaryOperation(bin_op) => bin_op.span,
+            ExpressionNode::UnaryOperation(unary_op) => unary_op.span,
+            ExpressionNode::Literal(literal) => literal.span,
+            ExpressionNode::Variable(_, span) => *span,
+            ExpressionNode::ProcedureCall(proc_call) => proc_call.span,
+            ExpressionNode::Cast(cast) => cast.span,
+            ExpressionNode::ArrayLiteral(_, span) => *span,
+            ExpressionNode::Tuple(_, span) => *span,
+        }
+    }
+
+    // Computes the value at compile time when the expression only involves literals and the given
+    // constants, with the same results the generated code would give.
+    pub fn fold(&self, constants: &HashMap<String, LiteralValue>) -> Option<LiteralValue> {
+        match self {
+            ExpressionNode::Literal(literal) => Some(literal.value.clone()),
+            ExpressionNode::Variable(name, _) => constants.get(name).cloned(),
+            ExpressionNode::UnaryOperation(unary_op) => match (&unary_op.operator, unary_op.operand.fold(constants)?) {
+                (Operator::Minus, LiteralValue::NumberValue(n)) => Some(LiteralValue::NumberValue(-n)),
+                (Operator::Plus, LiteralValue::NumberValue(n)) => Some(LiteralValue::NumberValue(n)),
+                (Operator::Not, LiteralValue::BooleanValue(b)) => Some(LiteralValue::BooleanValue(!b)),
+                _ => None,
+            },
+            ExpressionNode::BinaryOperation(bin_op) => {
+                let left = bin_op.left.fold(constants)?;
+                let right = bin_op.right.fold(constants)?;
+                match (&bin_op.operator, left, right) {
+                    (Operator::Plus, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l + r)),
+                    (Operator::Minus, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l - r)),
+                    (Operator::Times, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l * r)),
+                    (Operator::Over, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l / r)),
+                    (Operator::FloorOver, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue((l / r).floor())),
+                    (Operator::Mod, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l % r)),
+                    (Operator::Power, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::NumberValue(l.powf(r))),
+                    (Operator::Lt, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::BooleanValue(l < r)),
+                    (Operator::Gt, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::BooleanValue(l > r)),
+                    (Operator::Lte, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::BooleanValue(l <= r)),
+                    (Operator::Gte, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::BooleanValue(l >= r)),
+                    // Numbers are compared ordered, so `isnt` is false when either side is nan.
+                    (Operator::Isnt, LiteralValue::NumberValue(l), LiteralValue::NumberValue(r)) => Some(LiteralValue::BooleanValue(l < r || l > r)),
+                    (Operator::Is, l, r) if std::mem::discriminant(&l) == std::mem::discriminant(&r) => Some(LiteralValue::BooleanValue(l == r)),
+                    (Operator::Isnt, l, r) if std::mem::discriminant(&l) == std::mem::discriminant(&r) => Some(LiteralValue::BooleanValue(l != r)),
+                    (Operator::And, LiteralValue::BooleanValue(l), LiteralValue::BooleanValue(r)) => Some(LiteralValue::BooleanValue(l && r)),
+                    (Operator::Or, LiteralValue::BooleanValue(l), LiteralValue::BooleanValue(r)) => Some(LiteralValue::BooleanValue(l || r)),
+                    _ => None,
+                }
+            }
+            ExpressionNode::Cast(cast) => match (&cast.target_type, cast.operand.fold(constants)?) {
+                (TypeNode::BooleanType, LiteralValue::NumberValue(n)) => Some(LiteralValue::BooleanValue(n < 0.0 || n > 0.0)),
+                (TypeNode::NumberType, LiteralValue::BooleanValue(b)) => Some(LiteralValue::NumberValue(b as u8 as f64)),
+                (_, value) => Some(value),
+            },
+            ExpressionNode::ProcedureCall(_) | ExpressionNode::ArrayLiteral(..) | ExpressionNode::Tuple(..) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -80,23 +212,34 @@ pub struct BinaryOperationNode {
     pub left: ExpressionNode,
     pub operator: Operator,
     pub right: ExpressionNode,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct UnaryOperationNode {
     pub operator: Operator,
     pub operand: ExpressionNode,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastNode {
+    pub target_type: TypeNode,
+    pub operand: ExpressionNode,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LiteralNode {
     pub value: LiteralValue,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ProcedureCallNode {
     pub name: String,
     pub args: Vec<ExpressionNode>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -110,6 +253,9 @@ pub enum TypeNode {
     NumberType,
     BooleanType,
     StringType,
+    ArrayType(Box<TypeNode>, usize),
+    // Several values yielded together, only allowed as a return type.
+    TupleType(Vec<TypeNode>),
     VoidType
 }
 
@@ -119,3 +265,172 @@ pub enum LiteralValue {
     BooleanValue(bool),
     StringValue(String),
 }
+
+impl fmt::Display for TypeNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeNode::NumberType => write!(f, "number"),
+            TypeNode::BooleanType => write!(f, "boolean"),
+            TypeNode::StringType => write!(f, "string"),
+            TypeNode::ArrayType(element_type, length) => write!(f, "{}[{}]", element_type, length),
+            TypeNode::TupleType(element_types) => {
+                let names: Vec<String> = element_types.iter().map(ToString::to_string).collect();
+                write!(f, "({})", names.join(", "))
+            }
+            TypeNode::VoidType => write!(f, "void"),
+        }
+    }
+}
+
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // Very large and very small numbers switch to scientific notation instead of printing every digit.
+            LiteralValue::NumberValue(n) if *n != 0.0 && (n.abs() >= 1e15 || n.abs() < 1e-4) => write!(f, "Number({:e})", n),
+            LiteralValue::NumberValue(n) => write!(f, "Number({})", n),
+            LiteralValue::BooleanValue(b) => write!(f, "Boolean({})", b),
+            LiteralValue::StringValue(s) => write!(f, "String({:?})", s),
+        }
+    }
+}
+
+impl ProgramNode {
+    // The values of the constants that can be computed at compile time. The others, like arrays,
+    // only get their values when main starts.
+    pub fn folded_globals(&self) -> HashMap<String, LiteralValue> {
+        let mut folded = HashMap::new();
+        for global in &self.globals {
+            if let Some(value) = global.initializer.fold(&folded) {
+                folded.insert(global.name.clone(), value);
+            }
+        }
+        folded
+    }
+
+    // Compact rendering of the parse tree used by `--pretty`, one node per line with
+    // one level of indentation per nesting level.
+    pub fn render_tree(&self, output: &mut String) {
+        for global in &self.globals {
+            StatementNode::VariableDeclaration(global.clone()).render_tree(0, output);
+        }
+        for procedure in &self.procedures {
+            let params: Vec<String> = procedure.params.iter().map(|p| format!("{}: {}", p.name, p.param_type)).collect();
+            writeln!(output, "Procedure {}({}) -> {}", procedure.name, params.join(", "), procedure.return_type).unwrap();
+            procedure.body.render_tree(1, output);
+        }
+        writeln!(output, "Main {}", self.main.name).unwrap();
+        self.main.body.render_tree(1, output);
+    }
+}
+
+impl BlockNode {
+    fn render_tree(&self, depth: usize, output: &mut String) {
+        for statement in &self.statements {
+            statement.render_tree(depth, output);
+        }
+    }
+}
+
+impl StatementNode {
+    fn render_tree(&self, depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self {
+            StatementNode::VariableDeclaration(var_decl) => {
+                match &var_decl.var_type {
+                    Some(var_type) => writeln!(output, "{}Define {}: {}", indent, var_decl.name, var_type).unwrap(),
+                    None => writeln!(output, "{}Define {}", indent, var_decl.name).unwrap(),
+                }
+                var_decl.initializer.render_tree(depth + 1, output);
+            }
+            StatementNode::Destructure(destructure) => {
+                writeln!(output, "{}Define {}", indent, destructure.names.join(", ")).unwrap();
+                destructure.value.render_tree(depth + 1, output);
+            }
+            StatementNode::Assignment(assign) => {
+                writeln!(output, "{}Assign {}", indent, assign.targets.join(", ")).unwrap();
+                assign.value.render_tree(depth + 1, output);
+            }
+            StatementNode::Expression(expr) => expr.render_tree(depth, output),
+            StatementNode::Return(ret) => {
+                writeln!(output, "{}Yield", indent).unwrap();
+                if let Some(value) = &ret.value {
+                    value.render_tree(depth + 1, output);
+                }
+            }
+            StatementNode::Loop(loop_node) => {
+                writeln!(output, "{}Loop", indent).unwrap();
+                if let Some(condition) = &loop_node.condition {
+                    writeln!(output, "{}  While", indent).unwrap();
+                    condition.render_tree(depth + 2, output);
+                } else if let Some(count) = &loop_node.count {
+                    writeln!(output, "{}  Times", indent).unwrap();
+                    count.render_tree(depth + 2, output);
+                } else if let Some(range) = &loop_node.range {
+                    writeln!(output, "{}  Range {} ({})", indent, range.variable, if range.inclusive { "to" } else { "until" }).unwrap();
+                    range.from.render_tree(depth + 2, output);
+                    range.to.render_tree(depth + 2, output);
+                }
+                writeln!(output, "{}  Body", indent).unwrap();
+                loop_node.body.render_tree(depth + 2, output);
+                if let Some(otherwise) = &loop_node.otherwise {
+                    writeln!(output, "{}  Otherwise", indent).unwrap();
+                    otherwise.render_tree(depth + 2, output);
+                }
+            }
+            StatementNode::Conditional(cond) => {
+                writeln!(output, "{}When", indent).unwrap();
+                writeln!(output, "{}  Condition", indent).unwrap();
+                cond.condition.render_tree(depth + 2, output);
+                writeln!(output, "{}  Then", indent).unwrap();
+                cond.consequence.render_tree(depth + 2, output);
+                if let Some(alternative) = &cond.alternative {
+                    writeln!(output, "{}  Else", indent).unwrap();
+                    alternative.render_tree(depth + 2, output);
+                }
+            }
+            StatementNode::Break(_) => writeln!(output, "{}Leave", indent).unwrap(),
+            StatementNode::Continue(_) => writeln!(output, "{}Repeat", indent).unwrap(),
+        }
+    }
+}
+
+impl ExpressionNode {
+    fn render_tree(&self, depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self {
+            ExpressionNode::BinaryOperation(bin_op) => {
+                writeln!(output, "{}{:?}", indent, bin_op.operator).unwrap();
+                bin_op.left.render_tree(depth + 1, output);
+                bin_op.right.render_tree(depth + 1, output);
+            }
+            ExpressionNode::UnaryOperation(unary_op) => {
+                writeln!(output, "{}{:?}", indent, unary_op.operator).unwrap();
+                unary_op.operand.render_tree(depth + 1, output);
+            }
+            ExpressionNode::Literal(literal) => writeln!(output, "{}{}", indent, literal.value).unwrap(),
+            ExpressionNode::Variable(name, _) => writeln!(output, "{}Variable({})", indent, name).unwrap(),
+            ExpressionNode::ProcedureCall(proc_call) => {
+                writeln!(output, "{}Call {}", indent, proc_call.name).unwrap();
+                for arg in &proc_call.args {
+                    arg.render_tree(depth + 1, output);
+                }
+            }
+            ExpressionNode::Cast(cast) => {
+                writeln!(output, "{}Cast {}", indent, cast.target_type).unwrap();
+                cast.operand.render_tree(depth + 1, output);
+            }
+            ExpressionNode::ArrayLiteral(elements, _) => {
+                writeln!(output, "{}Array", indent).unwrap();
+                for element in elements {
+                    element.render_tree(depth + 1, output);
+                }
+            }
+            ExpressionNode::Tuple(values, _) => {
+                writeln!(output, "{}Tuple", indent).unwrap();
+                for value in values {
+                    value.render_tree(depth + 1, output);
+                }
+            }
+        }
+    }
+}
@@ -6,6 +6,10 @@ pub struct Parser {
     tokens: Vec<Token>, 
     current_index: usize,
     errors: Vec<String>,
+    // Without a procedure named after `entry`, use the first procedure as the entry point instead.
+    pub implicit_main: bool,
+    // Name of the procedure the program starts in, `main` unless embedding asks for another.
+    pub entry: String,
 }
 
 impl Parser {
@@ -14,6 +18,8 @@ impl Parser {
             tokens,
             current_index: 0,
             errors: Vec::new(),
+            implicit_main: false,
+            entry: "main".to_string(),
         }
     }
     fn advance(&mut self) -> Option<&Token> {
@@ -35,7 +41,7 @@ impl Parser {
                 Err(error_msg)
             }
         } else {
-            let error_msg = "Unexpected end of file".to_string();
+            let error_msg = format!("Unexpected end of file while expecting {}", describe_token_type(&expected_type));
             self.errors.push(error_msg.clone());
             Err(error_msg)
         }
@@ -48,6 +54,10 @@ impl Parser {
     fn current_token(&self) -> Option<&Token> {
         self.tokens.get(self.current_index)
     }
+
+    fn current_span(&self) -> Span {
+        self.current_token().map(span_of).unwrap_or_default()
+    }
     fn is_at_end(&self) -> bool {
         self.current_index >= self.tokens.len()
     }
@@ -64,19 +74,30 @@ impl Parser {
     }
 
     pub fn parse(&mut self) -> Result<ProgramNode, String> {
+        let mut globals = Vec::new();
         let mut procedures = Vec::new();
         let mut main_procedure = None;
 
         while !self.is_at_end() {
+            if let Some(Token { token_type: TokenType::Keyword(Keyword::Define), .. }) = self.current_token() {
+                match self.parse_variable_declaration() {
+                    Ok(var_decl) => globals.push(var_decl),
+                    Err(_) => self.synchronize(TokenType::Keyword(Keyword::Procedure)),
+                }
+                continue;
+            }
             match self.parse_procedure() {
                 Ok(proc) => {
-                    if proc.name == "main" {
+                    if proc.name == self.entry {
                         if main_procedure.is_some() {
-                            let error_msg = "Multiple main procedures not allowed.".to_string();
+                            let error_msg = format!("Multiple {} procedures not allowed.", self.entry);
                             self.errors.push(error_msg.clone());
                         } else if proc.return_type != TypeNode::VoidType {
                             let error_msg = "Main procedure cannot have a return type.".to_string();
                             self.errors.push(error_msg.clone());
+                        } else if proc.params.len() > 1 || proc.params.iter().any(|p| p.param_type != TypeNode::NumberType) {
+                            let error_msg = "Main procedure can only take a single number parameter, the argument count.".to_string();
+                            self.errors.push(error_msg.clone());
                         }
                         main_procedure = Some(proc);
                     } else {
@@ -89,16 +110,38 @@ impl Parser {
             }
         }
 
+        if main_procedure.is_none() && self.implicit_main && !procedures.is_empty() {
+            let first = procedures.remove(0);
+            if !first.params.is_empty() || first.return_type != TypeNode::VoidType {
+                let error_msg = format!(
+                    "Procedure '{}' cannot be used as main, it must take no parameters and have no return type.",
+                    first.name
+                );
+                self.errors.push(error_msg.clone());
+            }
+            main_procedure = Some(first);
+        }
+
         if self.errors.is_empty() {
             let main = match main_procedure {
                 Some(m) => MainProcedureNode {
+                    name: self.entry.clone(),
+                    params: m.params,
                     body: m.body,
+                    span: m.span,
                 },
                 None => {
-                    return Err("Missing required main procedure".to_string())
+                    // Listing what was found makes a misspelled entry procedure easy to spot.
+                    let names: Vec<&str> = procedures.iter().map(|p| p.name.as_str()).collect();
+                    return if names.is_empty() {
+                        Err(format!("Missing required {} procedure", self.entry))
+                    } else {
+                        Err(format!("Missing required {} procedure; defined procedures: {}", self.entry, names.join(", ")))
+                    };
                 }
             };
             Ok(ProgramNode {
+                globals,
                 procedures,
                 main
             })
@@ -107,15 +150,48 @@ impl Parser {
         }
     }
 
+    // Parses one line typed into the REPL: a single statement, or a bare expression such as `1 + 2`
+    // which couldn't start a statement otherwise. The line has to end with a `;` either way.
+    pub fn parse_repl_line(&mut self) -> Result<StatementNode, String> {
+        let statement = match self.current_token().map(|token| &token.token_type) {
+            Some(TokenType::Identifier(_) | TokenType::Keyword(_)) => self.parse_statement(),
+            _ => self.parse_expression().and_then(|expression| {
+                self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
+                Ok(StatementNode::Expression(expression))
+            }),
+        }?;
+
+        if let Some(token) = self.current_token() {
+            return Err(format!(
+                "Expected a single statement but found {:?} at line {}, column {}",
+                token.token_type, token.line, token.column
+            ));
+        }
+        Ok(statement)
+    }
+
     pub fn parse_procedure(&mut self) -> Result<ProcedureNode, String> {
+        let span = self.current_span();
+        let export = if let Some(Token { token_type: TokenType::Keyword(Keyword::Export), .. }) = self.current_token() {
+            self.advance();
+            true
+        } else {
+            false
+        };
         self.expect(TokenType::Keyword(Keyword::Procedure))?;
+        let noreturn = if let Some(Token { token_type: TokenType::Keyword(Keyword::NoReturn), .. }) = self.current_token() {
+            self.advance();
+            true
+        } else {
+            false
+        };
         let name = self.parse_identifier()?;
         let params = self.parse_parameters()?;
         let mut return_type = TypeNode::VoidType;
         if let Some(arrow) = self.current_token() {
             if let TokenType::Operator(Operator::Arrow) = arrow.token_type {
                 self.advance();
-                return_type = self.parse_type()?;
+                return_type = self.parse_return_type()?;
             }
         }
         let body = self.parse_block()?;
@@ -125,6 +201,9 @@ impl Parser {
             params,
             return_type,
             body,
+            noreturn,
+            export,
+            span,
         })
     }
 
@@ -145,7 +224,7 @@ impl Parser {
                 },
             },
             None => {
-                let error_msg = "Unexpected end of file".to_string();
+                let error_msg = "Unexpected end of file while expecting an identifier".to_string();
                 self.errors.push(error_msg.clone());
                 Err(error_msg)
             }
@@ -163,8 +242,14 @@ impl Parser {
         self.expect(TokenType::Delimiter(Delimiter::LeftParenthesis))?;
 
         while !self.current_token().map_or(false, |t| matches!(t.token_type, TokenType::Delimiter(Delimiter::RightParenthesis))) {
+            if self.is_at_end() {
+                let error_msg = "Unexpected end of file while parsing procedure parameters".to_string();
+                self.errors.push(error_msg.clone());
+                return Err(error_msg);
+            }
             let name = self.parse_identifier()?;
             self.expect(TokenType::Operator(Operator::Arrow))?;
+            self.reject_void_type("Parameters")?;
             let param_type = self.parse_type()?;
             params.push(ParameterNode { name, param_type });
 
@@ -180,6 +265,77 @@ impl Parser {
     }
 
     pub fn parse_type(&mut self) -> Result<TypeNode, String> {
+        let mut type_node = self.parse_scalar_type()?;
+        // `number[3]` is an array of three numbers, `number[2][3]` an array of two such arrays.
+        while let Some(TokenType::Delimiter(Delimiter::LeftBracket)) = self.current_token().map(|t| &t.token_type) {
+            self.advance();
+            let length = match self.current_token() {
+                Some(Token { token_type: TokenType::Literal(Literal::NumberLiteral(value)), .. })
+                    if value.fract() == 0.0 && *value >= 1.0 => *value as usize,
+                Some(token) => {
+                    let error_msg = format!(
+                        "Expected an array length but instead found '{:}' at line {}, column {}",
+                        token.lexeme, token.line, token.column
+                    );
+                    self.errors.push(error_msg.clone());
+                    return Err(error_msg);
+                }
+                None => {
+                    let error_msg = format!(
+                        "Unexpected end of file while parsing array length"
+                    );
+                    self.errors.push(error_msg.clone());
+                    return Err(error_msg);
+                }
+            };
+            self.advance();
+            self.expect(TokenType::Delimiter(Delimiter::RightBracket))?;
+            type_node = TypeNode::ArrayType(Box::new(type_node), length);
+        }
+        Ok(type_node)
+    }
+
+    // A procedure can also yield several values at once, e.g. `-> (number, number)`.
+    fn parse_return_type(&mut self) -> Result<TypeNode, String> {
+        if let Some(TokenType::Delimiter(Delimiter::LeftParenthesis)) = self.current_token().map(|t| &t.token_type) {
+            let span = self.current_span();
+            self.advance();
+            let mut element_types = vec![self.parse_type()?];
+            while let Some(TokenType::Delimiter(Delimiter::Comma)) = self.current_token().map(|t| &t.token_type) {
+                self.advance();
+                element_types.push(self.parse_type()?);
+            }
+            self.expect(TokenType::Delimiter(Delimiter::RightParenthesis))?;
+            if element_types.len() < 2 {
+                let error_msg = format!(
+                    "A tuple return type needs at least two types at line {}, column {}",
+                    span.line, span.column
+                );
+                self.errors.push(error_msg.clone());
+                return Err(error_msg);
+            }
+            return Ok(TypeNode::TupleType(element_types));
+        }
+        self.parse_type()
+    }
+
+    // `void` isn't a keyword, so without this check annotating a variable with it would only
+    // report a missing type keyword.
+    fn reject_void_type(&mut self, what: &str) -> Result<(), String> {
+        if let Some(token) = self.current_token() {
+            if token.token_type == TokenType::Identifier("void".to_string()) {
+                let error_msg = format!(
+                    "{} cannot have type 'void' at line {}, column {}",
+                    what, token.line, token.column
+                );
+                self.errors.push(error_msg.clone());
+                return Err(error_msg);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_scalar_type(&mut self) -> Result<TypeNode, String> {
         match self.current_token() {
             Some(token) => match &token.token_type {
                 TokenType::Keyword(Keyword::NumType) => {
@@ -244,22 +400,30 @@ impl Parser {
 
     fn parse_statement(&mut self) -> Result<StatementNode, String> {
         if self.is_at_end() {
-            return Err("Unexpected end of input while parsing statement.".to_string());
+            let error_msg = "Unexpected end of file while parsing statement".to_string();
+            self.errors.push(error_msg.clone());
+            return Err(error_msg);
         }
 
+        let span = self.current_span();
         match self.current_token() {
             Some(token) => match &token.token_type {
                 TokenType::Keyword(Keyword::Leave) => {
                     self.advance();
                     self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
-                    Ok(StatementNode::Break)
+                    Ok(StatementNode::Break(span))
                 }
                 TokenType::Keyword(Keyword::Repeat) => {
                     self.advance();
                     self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
-                    Ok(StatementNode::Continue)
+                    Ok(StatementNode::Continue(span))
+                }
+                TokenType::Keyword(Keyword::Define)
+                    if matches!(self.tokens.get(self.current_index + 2), Some(Token { token_type: TokenType::Delimiter(Delimiter::Comma), .. })) =>
+                {
+                    self.parse_destructure()
                 }
-                TokenType::Keyword(Keyword::Define) => self.parse_variable_declaration(),
+                TokenType::Keyword(Keyword::Define) => self.parse_variable_declaration().map(StatementNode::VariableDeclaration),
                 TokenType::Identifier(_) => self.parse_assignment_or_expression(),
                 TokenType::Keyword(Keyword::Yield) => self.parse_return(),
                 TokenType::Keyword(Keyword::Loop) => self.parse_loop(),
@@ -294,20 +458,22 @@ impl Parser {
             }
         } else {
             let error_msg = format!(
-                "unexpected end of file while parsing statement"
+                "Unexpected end of file while parsing statement"
             );
             self.errors.push(error_msg.clone());
             Err(error_msg)
         }
     }
     
-    fn parse_variable_declaration(&mut self) -> Result<StatementNode, String> {
+    fn parse_variable_declaration(&mut self) -> Result<VariableDeclarationNode, String> {
+        let span = self.current_span();
         self.expect(TokenType::Keyword(Keyword::Define))?;
 
         let name = self.parse_identifier()?;
 
         let var_type = if let Some(Token { token_type: TokenType::Operator(Operator::Arrow), .. }) = self.current_token() {
             self.advance(); 
+            self.reject_void_type("Variables")?;
             Some(self.parse_type()?)
         } else {
             None
@@ -318,37 +484,128 @@ impl Parser {
         let initializer = self.parse_expression()?;
         self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
 
-        Ok(StatementNode::VariableDeclaration(VariableDeclarationNode {
+        Ok(VariableDeclarationNode {
             name,
             var_type,
             initializer,
+            span,
+        })
+    }
+
+    fn parse_destructure(&mut self) -> Result<StatementNode, String> {
+        let span = self.current_span();
+        self.expect(TokenType::Keyword(Keyword::Define))?;
+
+        let mut names = vec![self.parse_identifier()?];
+        while let Some(TokenType::Delimiter(Delimiter::Comma)) = self.current_token().map(|t| &t.token_type) {
+            self.advance();
+            names.push(self.parse_identifier()?);
+        }
+
+        self.expect(TokenType::Operator(Operator::Assign))?;
+        let value = self.parse_expression()?;
+        self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
+
+        Ok(StatementNode::Destructure(DestructureNode {
+            names,
+            var_types: Vec::new(),
+            value,
+            span,
         }))
     }
 
     fn parse_assignment(&mut self) -> Result<StatementNode, String> {
-        let name = self.parse_identifier()?;
+        let span = self.current_span();
+        let mut targets = vec![self.parse_identifier()?];
 
         self.expect(TokenType::Operator(Operator::Assign))?;
 
+        // `a := b := 0` assigns to every name followed by `:=`.
+        while matches!(self.current_token(), Some(Token { token_type: TokenType::Identifier(_), .. }))
+            && matches!(self.peek(), Some(Token { token_type: TokenType::Operator(Operator::Assign), .. }))
+        {
+            targets.push(self.parse_identifier()?);
+            self.advance();
+        }
+
         let value = self.parse_expression()?;
         self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
 
         Ok(StatementNode::Assignment(AssignmentNode {
-            name,
+            targets,
             value,
+            span,
         }))
     }
 
     fn parse_loop(&mut self) -> Result<StatementNode, String> {
+        let span = self.current_span();
         self.expect(TokenType::Keyword(Keyword::Loop))?;
 
+        let mut condition = None;
+        let mut count = None;
+        let mut range = None;
+        match self.current_token() {
+            Some(Token { token_type: TokenType::Keyword(Keyword::While), .. }) => {
+                self.advance();
+                condition = Some(self.parse_expression()?);
+            }
+            Some(Token { token_type: TokenType::Delimiter(Delimiter::LeftBrace), .. }) => (),
+            // Like `times`, `from`, `to` and `until` are only special in this position.
+            Some(Token { token_type: TokenType::Identifier(_), .. })
+                if matches!(self.peek(), Some(Token { token_type: TokenType::Identifier(word), .. }) if word == "from") =>
+            {
+                let variable = self.parse_identifier()?;
+                self.advance();
+                let from = self.parse_expression()?;
+                let inclusive = match self.current_token() {
+                    Some(Token { token_type: TokenType::Identifier(word), .. }) if word == "to" => true,
+                    Some(Token { token_type: TokenType::Identifier(word), .. }) if word == "until" => false,
+                    Some(token) => {
+                        let error_msg = format!(
+                            "Expected 'to' or 'until' but found '{}' at line {}, column {}",
+                            token.lexeme, token.line, token.column
+                        );
+                        self.errors.push(error_msg.clone());
+                        return Err(error_msg);
+                    }
+                    None => {
+                        let error_msg = "Unexpected end of file while parsing loop range".to_string();
+                        self.errors.push(error_msg.clone());
+                        return Err(error_msg);
+                    }
+                };
+                self.advance();
+                let to = self.parse_expression()?;
+                range = Some(LoopRange { variable, from, to, inclusive });
+            }
+            // `times` is only special right after the count, so it stays usable as a name elsewhere.
+            _ => {
+                count = Some(self.parse_expression()?);
+                self.expect(TokenType::Identifier("times".to_string()))?;
+            }
+        }
+
         let body = self.parse_block()?;
 
+        let otherwise = if let Some(Token { token_type: TokenType::Keyword(Keyword::Otherwise), .. }) = self.current_token() {
+            self.advance();
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+
         Ok(StatementNode::Loop(LoopNode {
+            condition,
+            count,
+            range,
             body,
+            otherwise,
+            span,
         }))
     }
     fn parse_conditional(&mut self) -> Result<StatementNode, String> {
+        let span = self.current_span();
         self.expect(TokenType::Keyword(Keyword::When))?;
 
         let condition = self.parse_expression()?;
@@ -365,10 +622,12 @@ impl Parser {
             condition,
             consequence,
             alternative,
+            span,
         }))
     }
 
     fn parse_return(&mut self) -> Result<StatementNode, String> {
+        let span = self.current_span();
         self.expect(TokenType::Keyword(Keyword::Yield))?;
 
         if let Some(next_token) = self.current_token() {
@@ -376,21 +635,33 @@ impl Parser {
                 self.advance();
                 return Ok(StatementNode::Return(ReturnNode {
                     value: None,
+                    span,
                 }));
             }
         } else {
             let error_msg = format!(
-                "Unexpected end of file."
+                "Unexpected end of file while parsing yield statement"
             );
             self.errors.push(error_msg.clone());
             return Err(error_msg);
         }
 
-        let expression = self.parse_expression()?;
+        let mut expression = self.parse_expression()?;
+        // `yield a, b;` yields both values together as a tuple.
+        if let Some(TokenType::Delimiter(Delimiter::Comma)) = self.current_token().map(|t| &t.token_type) {
+            let tuple_span = expression.span();
+            let mut values = vec![expression];
+            while let Some(TokenType::Delimiter(Delimiter::Comma)) = self.current_token().map(|t| &t.token_type) {
+                self.advance();
+                values.push(self.parse_expression()?);
+            }
+            expression = ExpressionNode::Tuple(values, tuple_span);
+        }
         self.expect(TokenType::Delimiter(Delimiter::StatementEnd))?;
 
         Ok(StatementNode::Return(ReturnNode {
             value: Some(expression),
+            span,
         }))
     }
 
@@ -406,17 +677,23 @@ impl Parser {
         while !self.is_at_end() {
             if let Some(token) = self.current_token() {
                 let t = token.clone();
+                let span = span_of(&t);
                 if let TokenType::Operator(op) = t.token_type {
                     let op_precedence = op.get_precedence();
-                    if op_precedence < precedence {
+                    // `:=`, `->` and `not` have no precedence since they never join two operands,
+                    // e.g. in `a := 0 := b` the second `:=` ends the expression.
+                    if op_precedence == 0 || op_precedence < precedence {
                         break;
                     }
                     self.advance();
-                    let right = self.parse_binary_expression(op_precedence + 1)?;
+                    // `^` is right-associative, so `2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`
+                    let right_precedence = if op == Operator::Power { op_precedence } else { op_precedence + 1 };
+                    let right = self.parse_binary_expression(right_precedence)?;
                     left = ExpressionNode::BinaryOperation(Box::new(BinaryOperationNode  {
                         left,
                         operator: op.clone(),
-                        right
+                        right,
+                        span,
                     }));
                 } else {
                     break;
@@ -429,13 +706,20 @@ impl Parser {
     fn parse_unary_expression(&mut self) -> Result<ExpressionNode, String> {
         if let Some(token) = self.current_token() {
             let t = token.clone();
+            let span = span_of(&t);
             if let TokenType::Operator(op) = t.token_type {
-                if let Operator::Minus = op {
+                if let Operator::Minus | Operator::Plus | Operator::Not = op {
                     self.advance();
-                    let operand = self.parse_unary_expression()?;
+                    // A sign applies to a whole power like in maths, so `-2 ^ 2` is `-(2 ^ 2)`.
+                    let operand = if op == Operator::Not {
+                        self.parse_unary_expression()?
+                    } else {
+                        self.parse_binary_expression(Operator::Power.get_precedence())?
+                    };
                     return Ok(ExpressionNode::UnaryOperation(Box::new(UnaryOperationNode {
                         operator: op.clone(),
                         operand,
+                        span,
                     })));
                 }
             }
@@ -446,25 +730,32 @@ impl Parser {
     fn parse_primary(&mut self) -> Result<ExpressionNode, String> {
         if let Some(token) = self.current_token() {
             let t = token.clone();
+            let span = span_of(&t);
             match t.token_type {
                 TokenType::Identifier(name) => {
                     self.advance();
                     if let Some(TokenType::Delimiter(Delimiter::LeftParenthesis)) = self.current_token().map(|t| &t.token_type) {
-                        return self.parse_procedure_call(name.clone());
+                        return self.parse_procedure_call(name.clone(), span);
                     }
-                    Ok(ExpressionNode::Variable(name.clone()))
+                    Ok(ExpressionNode::Variable(name.clone(), span))
                 }
                 TokenType::Literal(Literal::NumberLiteral(value)) => {
                     self.advance();
-                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::NumberValue(value) }))
+                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::NumberValue(value), span }))
                 }
-                TokenType::Literal(Literal::StringLiteral(value)) => {
+                TokenType::Literal(Literal::StringLiteral(mut value)) => {
                     self.advance();
-                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::StringValue(value) }))
+                    // Adjacent literals are joined like in C, so long strings can be split across lines.
+                    // Their escapes were already resolved by the lexer, one literal at a time.
+                    while let Some(TokenType::Literal(Literal::StringLiteral(next))) = self.current_token().map(|t| &t.token_type) {
+                        value.push_str(next);
+                        self.advance();
+                    }
+                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::StringValue(value), span }))
                 }
                 TokenType::Literal(Literal::BooleanLiteral(value)) => {
                     self.advance();
-                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::BooleanValue(value) }))
+                    Ok(ExpressionNode::Literal(LiteralNode { value: LiteralValue::BooleanValue(value), span }))
                 }
                 TokenType::Delimiter(Delimiter::LeftParenthesis) => {
                     self.advance();
@@ -472,6 +763,30 @@ impl Parser {
                     self.expect(TokenType::Delimiter(Delimiter::RightParenthesis))?;
                     Ok(expr)
                 }
+                TokenType::Keyword(Keyword::NumType) | TokenType::Keyword(Keyword::BoolType) => {
+                    let target_type = self.parse_type()?;
+                    self.expect(TokenType::Delimiter(Delimiter::LeftParenthesis))?;
+                    let operand = self.parse_expression()?;
+                    self.expect(TokenType::Delimiter(Delimiter::RightParenthesis))?;
+                    Ok(ExpressionNode::Cast(Box::new(CastNode { target_type, operand, span })))
+                }
+                TokenType::Delimiter(Delimiter::LeftBracket) => {
+                    self.advance();
+                    let mut elements = Vec::new();
+                    if let Some(token) = self.current_token() {
+                        if token.token_type != TokenType::Delimiter(Delimiter::RightBracket) {
+                            loop {
+                                elements.push(self.parse_expression()?);
+                                if let Some(TokenType::Delimiter(Delimiter::RightBracket)) = self.current_token().map(|t| &t.token_type) {
+                                    break;
+                                }
+                                self.expect(TokenType::Delimiter(Delimiter::Comma))?;
+                            }
+                        }
+                    }
+                    self.expect(TokenType::Delimiter(Delimiter::RightBracket))?;
+                    Ok(ExpressionNode::ArrayLiteral(elements, span))
+                }
                 _ => {
                     let error_msg = format!(
                         "Unexpected token {:?} at line {}, column {}",
@@ -483,14 +798,14 @@ impl Parser {
             }
         } else {
             let error_msg = format!(
-                "Unexpected end of file."
+                "Unexpected end of file while parsing expression"
             );
             self.errors.push(error_msg.clone());
             Err(error_msg)
         }
     }
 
-    fn parse_procedure_call(&mut self, name: String) -> Result<ExpressionNode, String> {
+    fn parse_procedure_call(&mut self, name: String, span: Span) -> Result<ExpressionNode, String> {
         self.expect(TokenType::Delimiter(Delimiter::LeftParenthesis))?;
         let mut args = Vec::new();
 
@@ -508,6 +823,69 @@ impl Parser {
 
         self.expect(TokenType::Delimiter(Delimiter::RightParenthesis))?;
 
-        Ok(ExpressionNode::ProcedureCall(ProcedureCallNode { name, args }))
+        Ok(ExpressionNode::ProcedureCall(ProcedureCallNode { name, args, span }))
     }
 }
+
+fn span_of(token: &Token) -> Span {
+    Span { line: token.line, column: token.column }
+}
+
+// How a token the parser is waiting for reads in the source, for messages about a missing one.
+// Literals have no single spelling, so they are described by their kind instead.
+fn describe_token_type(token_type: &TokenType) -> String {
+    let text = match token_type {
+        TokenType::Delimiter(delimiter) => match delimiter {
+            Delimiter::LeftParenthesis => "(",
+            Delimiter::RightParenthesis => ")",
+            Delimiter::LeftBrace => "{",
+            Delimiter::RightBrace => "}",
+            Delimiter::LeftBracket => "[",
+            Delimiter::RightBracket => "]",
+            Delimiter::Comma => ",",
+            Delimiter::StatementEnd => ";",
+        },
+        TokenType::Operator(operator) => match operator {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Times => "*",
+            Operator::Over => "/",
+            Operator::FloorOver => "//",
+            Operator::Mod => "%",
+            Operator::Power => "^",
+            Operator::Lt => "<",
+            Operator::Gt => ">",
+            Operator::Lte => "<=",
+            Operator::Gte => ">=",
+            Operator::Arrow => "->",
+            Operator::Assign => ":=",
+            Operator::Is => "is",
+            Operator::Isnt => "isnt",
+            Operator::And => "and",
+            Operator::Or => "or",
+            Operator::Not => "not",
+        },
+        TokenType::Keyword(keyword) => match keyword {
+            Keyword::Procedure => "procedure",
+            Keyword::Define => "define",
+            Keyword::When => "when",
+            Keyword::Otherwise => "otherwise",
+            Keyword::Loop => "loop",
+            Keyword::While => "while",
+            Keyword::Yield => "yield",
+            Keyword::NumType => "number",
+            Keyword::BoolType => "boolean",
+            Keyword::StrType => "string",
+            Keyword::Leave => "leave",
+            Keyword::Repeat => "repeat",
+            Keyword::NoReturn => "noreturn",
+            Keyword::Export => "export",
+        },
+        TokenType::Identifier(name) => name,
+        TokenType::Literal(Literal::NumberLiteral(_)) => return "a number".to_string(),
+        TokenType::Literal(Literal::StringLiteral(_)) => return "a string".to_string(),
+        TokenType::Literal(Literal::BooleanLiteral(_)) => return "yes or no".to_string(),
+        TokenType::Invalid => return "an invalid token".to_string(),
+    };
+    format!("'{}'", text)
+}
@@ -1,18 +1,35 @@
 use std::collections::{HashMap, HashSet};
 
+use crate::builtins::{self, BUILTINS};
 use crate::parsing::node::*;
 use crate::lexing::token::Operator;
 
+use super::diagnostic::{Diagnostic, DiagnosticKind};
 use super::symbol_table::{SymbolInfo, SymbolTable, SymbolType};
 
+/// What to do when a variable declaration hides a variable or parameter of an enclosing scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ShadowingPolicy {
+    Allow,
+    Warn,
+    Deny,
+}
+
 pub struct SemanticAnalyzer {
     pub symbol_tables: HashMap<usize, SymbolTable>,
-    errors: Vec<String>,
-    pub warnings: Vec<String>,
+    errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
     in_loop: usize,
     current_procedure_return_type: TypeNode,
-    current_procedure_did_return: bool,
+    current_procedure_noreturn: bool,
+    current_procedure_export: bool,
+    // Constants whose values can't be computed at compile time and are only set when main starts.
+    runtime_constants: HashSet<String>,
+    current_span: Span,
     pub current_table_id: usize,
+    pub shadowing: ShadowingPolicy,
+    // Procedures declaring more parameters than this get a warning.
+    pub max_params: usize,
 }
 
 impl SemanticAnalyzer {
@@ -24,10 +41,24 @@ impl SemanticAnalyzer {
             in_loop: 0,
             current_table_id: 0,
             current_procedure_return_type: TypeNode::VoidType,
-            current_procedure_did_return: false,
+            current_procedure_noreturn: false,
+            current_procedure_export: false,
+            runtime_constants: HashSet::new(),
+            current_span: Span::default(),
+            shadowing: ShadowingPolicy::Deny,
+            max_params: 8,
         }
     }
 
+    // Records an error at the position of the node being analyzed.
+    fn error(&mut self, message: String) {
+        self.errors.push(Diagnostic { message, span: self.current_span, kind: DiagnosticKind::General });
+    }
+
+    fn warning(&mut self, message: String) {
+        self.warnings.push(Diagnostic { message, span: self.current_span, kind: DiagnosticKind::General });
+    }
+
     pub fn create_symbol_table(&mut self, parent_id: Option<usize>) -> usize {
         let new_id = self.symbol_tables.len();
         let new_table = SymbolTable::new(new_id, parent_id);
@@ -42,22 +73,76 @@ impl SemanticAnalyzer {
         new_id
     }
 
-    pub fn analyze(&mut self, program: &mut ProgramNode) -> Result<(), String> {
+    pub fn analyze(&mut self, program: &mut ProgramNode) -> Result<(), Vec<Diagnostic>> {
         self.analyze_program(program);
 
         if self.errors.is_empty() {
             Ok(())
         } else {
-            Err(self.errors.join("\n"))
+            Err(self.errors.clone())
         }
     }
 
-    fn analyze_program(&mut self, program: &mut ProgramNode) {
+    // Creates the root scope, which holds the builtins and the program's procedures.
+    fn create_root_table(&mut self) -> usize {
         let local_table_id = self.create_symbol_table(None);
         let local_table = self.symbol_tables.get_mut(&local_table_id).unwrap();
-        local_table.insert("print".into(), SymbolInfo { symbol_type: SymbolType::Procedure { return_type: TypeNode::VoidType, param_types: vec![TypeNode::StringType] }, used: true, initialized: true });
-        for procedure in &mut program.procedures {
-            self.analyze_procedure_declaration(procedure, local_table_id);
+        for builtin in BUILTINS {
+            local_table.insert(builtin.name.into(), SymbolInfo {
+                symbol_type: SymbolType::Procedure { return_type: builtin.return_type.clone(), param_types: builtin.param_types.to_vec(), noreturn: false },
+                used: true,
+                initialized: true,
+                span: Span::default(),
+            });
+        }
+        local_table_id
+    }
+
+    // Sets up the scopes for a REPL session and returns the one its variables are declared in.
+    pub fn begin_session(&mut self) -> usize {
+        let root_table_id = self.create_root_table();
+        self.create_symbol_table(Some(root_table_id))
+    }
+
+    // Analyzes one statement entered in the REPL against everything declared before it. An expression
+    // with a value becomes a print of that value, and a statement with errors declares nothing.
+    pub fn analyze_session_statement(&mut self, statement: &mut StatementNode, session_table_id: usize) -> Result<(), Vec<Diagnostic>> {
+        if let StatementNode::Expression(expression) = statement {
+            if !self.is_void_call(expression, session_table_id) {
+                let span = expression.span();
+                let template = ExpressionNode::Literal(LiteralNode { value: LiteralValue::StringValue("{}".to_string()), span });
+                let value = std::mem::replace(expression, template.clone());
+                *expression = ExpressionNode::ProcedureCall(ProcedureCallNode { name: "print".to_string(), args: vec![template, value], span });
+            }
+        }
+
+        let session_table = self.symbol_tables[&session_table_id].clone();
+        self.analyze_statement(statement, session_table_id);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            self.symbol_tables.insert(session_table_id, session_table);
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn analyze_program(&mut self, program: &mut ProgramNode) {
+        let local_table_id = self.create_root_table();
+        for global in &mut program.globals {
+            self.analyze_constant_declaration(global, local_table_id);
+        }
+        let folded = program.folded_globals();
+        self.runtime_constants = program.globals.iter().map(|global| global.name.clone()).filter(|name| !folded.contains_key(name)).collect();
+        // Every signature is registered before any body is analyzed, so procedures can call
+        // ones defined further down in the source.
+        let declared: Vec<bool> = program.procedures
+            .iter()
+            .map(|procedure| self.declare_procedure(procedure, local_table_id))
+            .collect();
+        for (procedure, declared) in program.procedures.iter_mut().zip(declared) {
+            if declared {
+                self.analyze_procedure_declaration(procedure, local_table_id);
+            }
         }
 
         self.analyze_main_procedure(&mut program.main, local_table_id);
@@ -65,11 +150,42 @@ impl SemanticAnalyzer {
         self.traverse_and_check(0);
     }
 
-    fn analyze_procedure_declaration(&mut self, procedure: &mut ProcedureNode, parent_table_id: usize) {
+    // Top-level constants live in the root scope next to the procedures. Their initializers are
+    // evaluated when the program starts, so they can't call procedures.
+    fn analyze_constant_declaration(&mut self, var_decl: &mut VariableDeclarationNode, root_table_id: usize) {
+        self.current_span = var_decl.span;
+        if Self::calls_procedure(&var_decl.initializer) {
+            self.error(format!("Constant '{}' cannot be initialized with a procedure call.", var_decl.name));
+            return;
+        }
+        self.analyze_variable_declaration(var_decl, root_table_id);
+
+        let root_table = self.symbol_tables.get_mut(&root_table_id).unwrap();
+        if let Some(symbol) = root_table.symbols.get_mut(&var_decl.name) {
+            if let SymbolType::Variable(var_type) = &symbol.symbol_type {
+                symbol.symbol_type = SymbolType::Constant(var_type.clone());
+            }
+        }
+    }
+
+    fn calls_procedure(expression: &ExpressionNode) -> bool {
+        match expression {
+            ExpressionNode::ProcedureCall(_) => true,
+            ExpressionNode::BinaryOperation(bin_op) => Self::calls_procedure(&bin_op.left) || Self::calls_procedure(&bin_op.right),
+            ExpressionNode::UnaryOperation(unary_op) => Self::calls_procedure(&unary_op.operand),
+            ExpressionNode::Cast(cast) => Self::calls_procedure(&cast.operand),
+            ExpressionNode::ArrayLiteral(elements, _) | ExpressionNode::Tuple(elements, _) => elements.iter().any(Self::calls_procedure),
+            ExpressionNode::Literal(_) | ExpressionNode::Variable(..) => false,
+        }
+    }
+
+    // Adds the procedure's signature to the root scope, returning false if the name is taken.
+    fn declare_procedure(&mut self, procedure: &ProcedureNode, parent_table_id: usize) -> bool {
+        self.current_span = procedure.span;
         let parent_table = self.symbol_tables.get(&parent_table_id).unwrap();
         if parent_table.lookup(&procedure.name, &self.symbol_tables).is_some() {
-            self.errors.push(format!("Procedure '{}' is already declared.", procedure.name));
-            return;
+            self.error(format!("Procedure '{}' is already declared.", procedure.name));
+            return false;
         }
 
         let mut_parent_table = self.symbol_tables.get_mut(&parent_table_id).unwrap();
@@ -80,12 +196,18 @@ impl SemanticAnalyzer {
                 symbol_type: SymbolType::Procedure {
                     return_type: procedure.return_type.clone(),
                     param_types: procedure.params.iter().map(|p| p.param_type.clone()).collect(),
+                    noreturn: procedure.noreturn,
                 },
                 used: false,
                 initialized: true,
+                span: procedure.span,
             },
         );
+        true
+    }
 
+    fn analyze_procedure_declaration(&mut self, procedure: &mut ProcedureNode, parent_table_id: usize) {
+        self.current_span = procedure.span;
         let local_table_id = self.create_symbol_table(Some(parent_table_id));
         let local_symbol_table = self.symbol_tables.get_mut(&local_table_id).unwrap();
 
@@ -93,29 +215,123 @@ impl SemanticAnalyzer {
             local_symbol_table.insert(
                 param.name.clone(),
                 SymbolInfo {
-                    symbol_type: SymbolType::Variable(param.param_type.clone()),
+                    symbol_type: SymbolType::Parameter {
+                        param_type: param.param_type.clone(),
+                        procedure: procedure.name.clone(),
+                    },
                     used: false,
                     initialized: true,
+                    span: procedure.span,
                 },
             );
         }
 
+        if procedure.noreturn && procedure.return_type != TypeNode::VoidType {
+            self.error(format!("Procedure '{}' is marked noreturn and cannot have a return type.", procedure.name));
+        }
+        // Inside the body the parameter wins the lookup, so the procedure can't call itself anymore.
+        if procedure.params.iter().any(|param| param.name == procedure.name) {
+            self.warning(format!("Parameter '{}' shadows the enclosing procedure name.", procedure.name));
+        }
+        if procedure.params.len() > self.max_params {
+            self.warning(format!(
+                "Procedure '{}' has {} parameters, more than the maximum of {}.",
+                procedure.name, procedure.params.len(), self.max_params
+            ));
+        }
+
         self.current_procedure_return_type = procedure.return_type.clone();
+        self.current_procedure_noreturn = procedure.noreturn;
+        self.current_procedure_export = procedure.export;
         self.analyze_block(&mut procedure.body, local_table_id);
-
-        let return_statement = match procedure.return_type {
-            TypeNode::NumberType => ReturnNode { value: Some(ExpressionNode::Literal(LiteralNode { value: LiteralValue::NumberValue(0.0) })) },
-            TypeNode::BooleanType => ReturnNode { value: Some(ExpressionNode::Literal(LiteralNode { value: LiteralValue::BooleanValue(false) })) },
-            TypeNode::StringType => ReturnNode { value: Some(ExpressionNode::Literal(LiteralNode { value: LiteralValue::StringValue("".to_string()) })) },
-            TypeNode::VoidType => ReturnNode { value: None },
-        };
-        procedure.body.statements.push(StatementNode::Return(return_statement));
+        self.current_procedure_noreturn = false;
+        self.current_procedure_export = false;
+
+        if procedure.noreturn {
+            // There is nothing to return to, so the body must never get to its end.
+            if !self.block_always_yields(&procedure.body) {
+                self.current_span = procedure.span;
+                self.error(format!("Noreturn procedure '{}' can return.", procedure.name));
+            }
+        } else if procedure.return_type == TypeNode::VoidType {
+            procedure.body.statements.push(StatementNode::Return(ReturnNode { value: None, span: procedure.span }));
+        } else if !self.block_always_yields(&procedure.body) {
+            self.current_span = procedure.span;
+            self.error(format!("Procedure '{}' does not yield a value on every path.", procedure.name));
+        }
 
         self.current_procedure_return_type = TypeNode::VoidType;
     }
 
+    fn is_comparable(type_node: &TypeNode) -> bool {
+        match type_node {
+            TypeNode::NumberType | TypeNode::BooleanType | TypeNode::StringType => true,
+            TypeNode::ArrayType(element_type, _) => Self::is_comparable(element_type),
+            TypeNode::TupleType(_) => false,
+            TypeNode::VoidType => false,
+        }
+    }
+
+    // Whether every path through the block ends in a `yield` (or never finishes at all),
+    // so control can't fall off its end.
+    fn block_always_yields(&self, block: &BlockNode) -> bool {
+        for statement in &block.statements {
+            let diverges = match statement {
+                StatementNode::Return(_) => true,
+                StatementNode::Conditional(cond) => {
+                    self.block_always_yields(&cond.consequence)
+                        && cond.alternative.as_ref().map_or(false, |block| self.block_always_yields(block))
+                }
+                // An unconditional loop only finishes through a `leave`, any other loop also finishes
+                // through its `otherwise` block when it has one.
+                StatementNode::Loop(loop_node) => {
+                    !Self::block_leaves(&loop_node.body)
+                        && ((loop_node.condition.is_none() && loop_node.count.is_none() && loop_node.range.is_none())
+                            || loop_node.otherwise.as_ref().map_or(false, |block| self.block_always_yields(block)))
+                }
+                // Noreturn procedures can only be declared at the top level
+                statement => self.is_diverging_call(statement, 0),
+            };
+            if diverges {
+                return true;
+            }
+            if let StatementNode::Break(_) | StatementNode::Continue(_) = statement {
+                return false;
+            }
+        }
+        false
+    }
+
+    // Whether the block contains a `leave` for the loop it belongs to. `leave`s inside the body of a
+    // nested loop only exit that loop, but its `otherwise` block runs outside of it.
+    fn block_leaves(block: &BlockNode) -> bool {
+        block.statements.iter().any(|statement| match statement {
+            StatementNode::Break(_) => true,
+            StatementNode::Conditional(cond) => {
+                Self::block_leaves(&cond.consequence) || cond.alternative.as_ref().map_or(false, Self::block_leaves)
+            }
+            StatementNode::Loop(loop_node) => loop_node.otherwise.as_ref().map_or(false, Self::block_leaves),
+            _ => false,
+        })
+    }
+
     fn analyze_main_procedure(&mut self, main: &mut MainProcedureNode, parent_table_id: usize) {
         let local_table_id = self.create_symbol_table(Some(parent_table_id));
+        let local_symbol_table = self.symbol_tables.get_mut(&local_table_id).unwrap();
+        for param in &main.params {
+            local_symbol_table.insert(
+                param.name.clone(),
+                SymbolInfo {
+                    symbol_type: SymbolType::Parameter {
+                        param_type: param.param_type.clone(),
+                        procedure: main.name.clone(),
+                    },
+                    used: false,
+                    initialized: true,
+                    span: main.span,
+                },
+            );
+        }
         self.analyze_block(&mut main.body, local_table_id);
     }
 
@@ -123,51 +339,101 @@ impl SemanticAnalyzer {
         let local_table_id = self.create_symbol_table(Some(parent_table_id));
 
         let mut code_is_reachable = true;
+        // The loop control keyword that made the rest of the block unreachable, if it was one.
+        let mut left_by = None;
 
         for statement in &mut block.statements {
             if !code_is_reachable {
-                self.warnings.push("Unreachable code detected.".to_string());
+                self.current_span = statement.span();
+                match left_by {
+                    Some(keyword) => self.warning(format!("Statement after '{}' will never execute.", keyword)),
+                    None => self.warning("Unreachable code detected.".to_string()),
+                }
                 break;
             }
 
             self.analyze_statement(statement, local_table_id);
 
-            if matches!(statement, StatementNode::Return(_) | StatementNode::Break | StatementNode::Continue) {
+            if matches!(statement, StatementNode::Return(_) | StatementNode::Break(_) | StatementNode::Continue(_))
+                || self.is_diverging_call(statement, local_table_id)
+            {
                 code_is_reachable = false;
+                left_by = match statement {
+                    StatementNode::Break(_) => Some("leave"),
+                    StatementNode::Continue(_) => Some("repeat"),
+                    _ => None,
+                };
             }
         }
     }
 
     fn analyze_statement(&mut self, statement: &mut StatementNode, parent_table_id: usize) {
+        self.current_span = statement.span();
         match statement {
             StatementNode::VariableDeclaration(var_decl) => self.analyze_variable_declaration(var_decl, parent_table_id),
+            StatementNode::Destructure(destructure) => self.analyze_destructure(destructure, parent_table_id),
             StatementNode::Assignment(assign) => self.analyze_assignment(assign, parent_table_id),
-            StatementNode::Expression(expression) => { self.analyze_expression(expression, parent_table_id); },
+            StatementNode::Expression(expression) => {
+                let expression_type = self.analyze_expression(expression, parent_table_id);
+                if let ExpressionNode::ProcedureCall(proc_call) = expression {
+                    if expression_type != TypeNode::VoidType {
+                        self.warning(format!("Return value of '{}' is ignored.", proc_call.name));
+                    }
+                }
+            },
             StatementNode::Return(ret) => self.analyze_return_statement(ret, parent_table_id),
             StatementNode::Loop(loop_node) => self.analyze_loop(loop_node, parent_table_id),
             StatementNode::Conditional(cond) => self.analyze_conditional(cond, parent_table_id),
-            StatementNode::Break => self.analyze_break(),
-            StatementNode::Continue => self.analyze_continue(),
+            StatementNode::Break(_) => self.analyze_break(),
+            StatementNode::Continue(_) => self.analyze_continue(),
+        }
+    }
+
+    // Whether a variable called `name` may be declared in the given scope, reporting why not otherwise.
+    fn check_declaration(&mut self, name: &str, table_id: usize) -> bool {
+        let symbol_table = self.symbol_tables.get(&table_id).unwrap();
+        if let Some(existing) = symbol_table.lookup(name, &self.symbol_tables) {
+            let shadows_variable = !symbol_table.symbols.contains_key(name)
+                && matches!(existing.symbol_type, SymbolType::Variable(_) | SymbolType::Constant(_) | SymbolType::Parameter { .. });
+            let shadowing = format!("Variable '{}' shadows a variable of an enclosing scope.", name);
+            match (shadows_variable, self.shadowing) {
+                (true, ShadowingPolicy::Allow) => (),
+                (true, ShadowingPolicy::Warn) => self.warning(shadowing),
+                (true, ShadowingPolicy::Deny) => {
+                    self.error(shadowing);
+                    return false;
+                }
+                (false, _) => {
+                    self.error(format!("Variable '{}' is already declared in this scope.", name));
+                    return false;
+                }
+            }
         }
+        true
     }
 
     fn analyze_variable_declaration(&mut self, var_decl: &mut VariableDeclarationNode, parent_table_id: usize) {
-        let symbol_table = self.symbol_tables.get(&parent_table_id).unwrap();
-        if symbol_table.lookup(&var_decl.name, &self.symbol_tables).is_some() {
-            self.errors.push(format!("Variable '{}' is already declared in this scope.", var_decl.name));
+        if !self.check_declaration(&var_decl.name, parent_table_id) {
             return;
         }
 
         let initializer_type = self.analyze_expression(&mut var_decl.initializer, parent_table_id);
 
         if initializer_type == TypeNode::VoidType {
-            self.errors.push("Cannot assign void to a variable.".to_string());
+            self.error("Cannot assign void to a variable.".to_string());
+            return;
+        }
+        if let TypeNode::TupleType(element_types) = &initializer_type {
+            self.error(format!(
+                "Cannot store {} values in the single variable '{}', declare one variable for each value instead.",
+                element_types.len(), var_decl.name
+            ));
             return;
         }
 
         if let Some(var_type) = &var_decl.var_type {
             if &initializer_type != var_type {
-                self.errors.push(format!(
+                self.error(format!(
                     "Type mismatch: expected {:?}, found {:?} for variable '{}'.",
                     var_type, initializer_type, var_decl.name
                 ));
@@ -183,31 +449,72 @@ impl SemanticAnalyzer {
                 symbol_type: SymbolType::Variable(initializer_type),
                 used: false,
                 initialized: true,
+                span: var_decl.span,
             },
         );
     }
 
+    fn analyze_destructure(&mut self, destructure: &mut DestructureNode, parent_table_id: usize) {
+        let value_type = self.analyze_expression(&mut destructure.value, parent_table_id);
+        let element_types = match value_type {
+            TypeNode::TupleType(element_types) if element_types.len() == destructure.names.len() => element_types,
+            TypeNode::TupleType(element_types) => {
+                self.error(format!(
+                    "Cannot destructure {} values into {} variables.",
+                    element_types.len(), destructure.names.len()
+                ));
+                return;
+            }
+            other => {
+                self.error(format!("Only the values yielded together by a procedure can be destructured, found {}.", other));
+                return;
+            }
+        };
+
+        for (name, var_type) in destructure.names.iter().zip(&element_types) {
+            if self.check_declaration(name, parent_table_id) {
+                self.symbol_tables.get_mut(&parent_table_id).unwrap().insert(
+                    name.clone(),
+                    SymbolInfo {
+                        symbol_type: SymbolType::Variable(var_type.clone()),
+                        used: false,
+                        initialized: true,
+                        span: destructure.span,
+                    },
+                );
+            }
+        }
+        destructure.var_types = element_types;
+    }
+
     fn analyze_assignment(&mut self, assign: &mut AssignmentNode, parent_table_id: usize) {
-        let (var_type_opt, symbol_name) = {
+        let mut target_types = Vec::new();
+        for name in &assign.targets {
             let symbol_table = self.symbol_tables.get(&parent_table_id).unwrap();
-            if let Some(symbol_info) = symbol_table.lookup(&assign.name, &self.symbol_tables) {
+            if let Some(symbol_info) = symbol_table.lookup(name, &self.symbol_tables) {
                 match &symbol_info.symbol_type {
-                    SymbolType::Variable(var_type) => (Some(var_type.clone()), assign.name.clone()),
-                    _ => {
-                        self.errors.push(format!("'{}' is not a variable and cannot be assigned to.", assign.name));
-                        (None, assign.name.clone())
-                    }
+                    SymbolType::Variable(var_type) | SymbolType::Parameter { param_type: var_type, .. } => target_types.push((name.clone(), var_type.clone())),
+                    SymbolType::Constant(_) => self.error(format!("'{}' is a constant and cannot be assigned to.", name)),
+                    _ => self.error(format!("'{}' is not a variable and cannot be assigned to.", name)),
                 }
             } else {
-                self.errors.push(format!("Variable '{}' is not declared.", assign.name));
-                (None, assign.name.clone())
+                self.error(self.not_declared_message("Variable", name, parent_table_id));
             }
-        };
 
-        if let Some(var_type) = var_type_opt {
-            let value_type = self.analyze_expression(&mut assign.value, parent_table_id);
+            if let ExpressionNode::Variable(value_name, _) = &assign.value {
+                if value_name == name {
+                    self.warning(format!("Self-assignment of '{}' has no effect.", name));
+                }
+            }
+        }
+
+        if target_types.is_empty() {
+            return;
+        }
+        let value_type = self.analyze_expression(&mut assign.value, parent_table_id);
+        for (symbol_name, var_type) in target_types {
             if value_type != var_type {
-                self.errors.push(format!(
+                self.error(format!(
                     "Type mismatch in assignment: expected {:?}, found {:?} for variable '{}'.",
                     var_type, value_type, symbol_name
                 ));
@@ -215,18 +522,31 @@ impl SemanticAnalyzer {
         }
     }
 
+    fn is_diverging_call(&self, statement: &StatementNode, parent_table_id: usize) -> bool {
+        if let StatementNode::Expression(ExpressionNode::ProcedureCall(proc_call)) = statement {
+            let symbol_table = self.symbol_tables.get(&parent_table_id).unwrap();
+            if let Some(SymbolInfo { symbol_type: SymbolType::Procedure { noreturn, .. }, .. }) = symbol_table.lookup(&proc_call.name, &self.symbol_tables) {
+                return *noreturn;
+            }
+        }
+        false
+    }
+
     fn analyze_return_statement(&mut self, ret: &mut ReturnNode, parent_table_id: usize) {
+        if self.current_procedure_noreturn {
+            self.error("'yield' statement used inside a noreturn procedure.".to_string());
+        }
         if let Some(return_value) = &mut ret.value {
             let return_type = self.analyze_expression(return_value, parent_table_id);
             if return_type != self.current_procedure_return_type {
-                self.errors.push(format!(
+                self.error(format!(
                     "Type mismatch in 'yield' statement: expected {:?}, found {:?}.",
                     self.current_procedure_return_type, return_type
                 ));
             }
         } else {
             if self.current_procedure_return_type != TypeNode::VoidType {
-                self.errors.push(format!(
+                self.error(format!(
                     "Type mismatch in 'yield' statement: expected {:?}, found void.",
                     self.current_procedure_return_type
                 ));
@@ -235,50 +555,149 @@ impl SemanticAnalyzer {
     }
 
     fn analyze_loop(&mut self, loop_node: &mut LoopNode, parent_table_id: usize) {
+        if let Some(condition) = &mut loop_node.condition {
+            let condition_type = self.analyze_value(condition, parent_table_id);
+            self.check_condition_type("Loop condition", condition, &condition_type);
+        }
+        if let Some(count) = &mut loop_node.count {
+            let count_type = self.analyze_value(count, parent_table_id);
+            if count_type != TypeNode::NumberType && count_type != TypeNode::VoidType {
+                self.error("Loop count must be of type 'number'.".to_string());
+            }
+        }
+
+        // The counter of `loop i from A to B` gets a scope of its own around the body.
+        let mut body_table_id = parent_table_id;
+        if let Some(range) = &mut loop_node.range {
+            for bound in [&mut range.from, &mut range.to] {
+                let bound_type = self.analyze_value(bound, parent_table_id);
+                if bound_type != TypeNode::NumberType && bound_type != TypeNode::VoidType {
+                    self.error("Loop bounds must be of type 'number'.".to_string());
+                }
+            }
+
+            body_table_id = self.create_symbol_table(Some(parent_table_id));
+            if self.check_declaration(&range.variable, body_table_id) {
+                self.symbol_tables.get_mut(&body_table_id).unwrap().insert(
+                    range.variable.clone(),
+                    SymbolInfo {
+                        symbol_type: SymbolType::Variable(TypeNode::NumberType),
+                        used: false,
+                        initialized: true,
+                        span: loop_node.span,
+                    },
+                );
+            }
+        }
+
         self.in_loop += 1;
-        self.analyze_block(&mut loop_node.body, parent_table_id);
+        self.analyze_block(&mut loop_node.body, body_table_id);
         self.in_loop -= 1;
+
+        // The `otherwise` block runs after the loop is done, so `leave` and `repeat` in it belong to an enclosing loop.
+        if let Some(otherwise) = &mut loop_node.otherwise {
+            self.analyze_block(otherwise, parent_table_id);
+        }
     }
 
     fn analyze_conditional(&mut self, cond: &mut ConditionalNode, parent_table_id: usize) {
-        let condition_type = self.analyze_expression(&mut cond.condition, parent_table_id);
-        if condition_type != TypeNode::BooleanType {
-            self.errors.push(format!("Conditional expression must be of type 'boolean'."));
-        }
+        let condition_type = self.analyze_value(&mut cond.condition, parent_table_id);
+        self.check_condition_type("Conditional expression", &cond.condition, &condition_type);
 
         self.analyze_block(&mut cond.consequence, parent_table_id);
 
         if let Some(alternative) = &mut cond.alternative {
             self.analyze_block(alternative, parent_table_id);
         }
+    }
 
-        if cond.consequence.statements.iter().any(|s| matches!(s, StatementNode::Return(_)))
-            && cond.alternative.as_ref().map_or(false, |block| block.statements.iter().any(|s| matches!(s, StatementNode::Return(_))))
-        {
-            self.current_procedure_did_return = true;
+    // Reports a non-boolean condition at the condition itself. Numbers aren't truthy like in C,
+    // so those get a hint to compare them instead.
+    fn check_condition_type(&mut self, what: &str, condition: &ExpressionNode, condition_type: &TypeNode) {
+        if *condition_type == TypeNode::BooleanType || *condition_type == TypeNode::VoidType {
+            return;
         }
+        let enclosing_span = std::mem::replace(&mut self.current_span, condition.span());
+        if *condition_type == TypeNode::NumberType {
+            self.error(format!("{} must be of type 'boolean', found 'number'. Use a comparison like `x isnt 0` instead.", what));
+        } else {
+            self.error(format!("{} must be of type 'boolean', found '{}'.", what, condition_type));
+        }
+        self.current_span = enclosing_span;
     }
 
     fn analyze_break(&mut self) {
         if self.in_loop == 0 {
-            self.errors.push("'leave' statement used outside of a loop.".to_string());
+            self.error("'leave' statement used outside of a loop.".to_string());
         }
     }
 
     fn analyze_continue(&mut self) {
         if self.in_loop == 0 {
-            self.errors.push("'repeat' statement used outside of a loop.".to_string());
+            self.error("'repeat' statement used outside of a loop.".to_string());
+        }
+    }
+
+    // Analyzes an expression whose value is used, reporting calls to void procedures. A void type
+    // from anything else means the expression already reported an error, so callers skip their
+    // own type checks on void to keep one mistake from cascading into several errors.
+    fn analyze_value(&mut self, expr: &mut ExpressionNode, parent_table_id: usize) -> TypeNode {
+        let value_type = self.analyze_expression(expr, parent_table_id);
+        if value_type == TypeNode::VoidType && self.is_void_call(expr, parent_table_id) {
+            let enclosing_span = std::mem::replace(&mut self.current_span, expr.span());
+            self.error("Void value used in expression.".to_string());
+            self.current_span = enclosing_span;
+        }
+        value_type
+    }
+
+    fn is_void_call(&self, expr: &ExpressionNode, parent_table_id: usize) -> bool {
+        let ExpressionNode::ProcedureCall(proc_call) = expr else {
+            return false;
+        };
+        if let Some(builtin) = builtins::find(&proc_call.name) {
+            return builtin.return_type == TypeNode::VoidType;
         }
+        let symbol_table = self.symbol_tables.get(&parent_table_id).unwrap();
+        matches!(
+            symbol_table.lookup(&proc_call.name, &self.symbol_tables),
+            Some(SymbolInfo { symbol_type: SymbolType::Procedure { return_type: TypeNode::VoidType, .. }, .. })
+        )
     }
 
     fn analyze_expression(&mut self, expr: &mut ExpressionNode, parent_table_id: usize) -> TypeNode {
+        // Errors about this expression point at it, and the enclosing one's position is restored afterwards.
+        let enclosing_span = std::mem::replace(&mut self.current_span, expr.span());
+        let expr_type = self.analyze_expression_kind(expr, parent_table_id);
+        self.current_span = enclosing_span;
+        expr_type
+    }
+
+    fn analyze_expression_kind(&mut self, expr: &mut ExpressionNode, parent_table_id: usize) -> TypeNode {
         match expr {
             ExpressionNode::BinaryOperation(bin_op) => {
-                let left_type = self.analyze_expression(&mut bin_op.left, parent_table_id);
-                let right_type = self.analyze_expression(&mut bin_op.right, parent_table_id);
+                let left_type = self.analyze_value(&mut bin_op.left, parent_table_id);
+                let right_type = self.analyze_value(&mut bin_op.right, parent_table_id);
+
+                if left_type == TypeNode::VoidType || right_type == TypeNode::VoidType {
+                    return match bin_op.operator {
+                        Operator::Plus | Operator::Minus | Operator::Times | Operator::Over | Operator::FloorOver | Operator::Mod | Operator::Power => TypeNode::NumberType,
+                        _ => TypeNode::BooleanType,
+                    };
+                }
+
+                if let (TypeNode::ArrayType(_, left_length), TypeNode::ArrayType(_, right_length)) = (&left_type, &right_type) {
+                    if left_length != right_length {
+                        self.error(format!(
+                            "Cannot compare arrays of different lengths: {} vs {}.",
+                            left_length, right_length
+                        ));
+                        return TypeNode::BooleanType;
+                    }
+                }
 
                 if left_type != right_type {
-                    self.errors.push(format!(
+                    self.error(format!(
                         "Type mismatch in binary operation: {:?} vs {:?}.",
                         left_type, right_type
                     ));
@@ -286,18 +705,27 @@ impl SemanticAnalyzer {
                 }
 
                 match bin_op.operator {
-                    Operator::Plus | Operator::Minus | Operator::Times | Operator::Over | Operator::Mod => {
+                    Operator::Plus | Operator::Minus | Operator::Times | Operator::Over | Operator::FloorOver | Operator::Mod | Operator::Power => {
                         if left_type != TypeNode::NumberType {
-                            self.errors.push(format!(
+                            self.error(format!(
                                 "Operator {:?} expects number types.",
                                 bin_op.operator
                             ));
                         }
+                        // Only a literal zero is caught here, a divisor that is zero at runtime is left to the program.
+                        if matches!(bin_op.operator, Operator::Over | Operator::FloorOver | Operator::Mod) {
+                            if let ExpressionNode::Literal(LiteralNode { value: LiteralValue::NumberValue(divisor), span }) = &bin_op.right {
+                                if *divisor == 0.0 {
+                                    self.current_span = *span;
+                                    self.error("Division by zero.".to_string());
+                                }
+                            }
+                        }
                         TypeNode::NumberType
                     }
                     Operator::Lt | Operator::Gt | Operator::Lte | Operator::Gte => {
                         if left_type != TypeNode::NumberType || right_type != TypeNode::NumberType {
-                            self.errors.push(format!(
+                            self.error(format!(
                                 "Operator {:?} expects number types.",
                                 bin_op.operator
                             ));
@@ -306,7 +734,7 @@ impl SemanticAnalyzer {
                     }
                     Operator::And | Operator::Or => {
                         if left_type != TypeNode::BooleanType || right_type != TypeNode::BooleanType {
-                            self.errors.push(format!(
+                            self.error(format!(
                                 "Operator {:?} expects boolean types.",
                                 bin_op.operator
                             ));
@@ -314,49 +742,118 @@ impl SemanticAnalyzer {
                         TypeNode::BooleanType
                     }
                     Operator::Is | Operator::Isnt => {
-                        // operands should be boolean or float
-                        if left_type != TypeNode::BooleanType && left_type != TypeNode::NumberType {
-                            self.errors.push(format!(
-                                "Operator {:?} expects boolean or number types.",
-                                bin_op.operator
-                            ));
-                        } else if right_type != TypeNode::BooleanType && right_type != TypeNode::NumberType {
-                            self.errors.push(format!(
-                                "Operator {:?} expects boolean or number types.",
+                        // operands should be boolean, float, string or arrays of those, compared element-wise
+                        if !Self::is_comparable(&left_type) {
+                            self.error(format!(
+                                "Operator {:?} expects boolean, number, string or array types.",
                                 bin_op.operator
                             ));
                         }
+                        // `flag is yes` is just `flag`, and `flag is no` is `not flag`
+                        let is_boolean_literal = |expr: &ExpressionNode| matches!(
+                            expr,
+                            ExpressionNode::Literal(LiteralNode { value: LiteralValue::BooleanValue(_), .. })
+                        );
+                        if left_type == TypeNode::BooleanType
+                            && (is_boolean_literal(&bin_op.left) || is_boolean_literal(&bin_op.right)) {
+                            self.warning("Comparison with boolean literal is redundant; use the value directly.".to_string());
+                        }
                         TypeNode::BooleanType
                     },
                     _ => left_type,
                 }
             }
             ExpressionNode::UnaryOperation(unary_op) => {
-                let operand_type = self.analyze_expression(&mut unary_op.operand, parent_table_id);
+                let operand_type = self.analyze_value(&mut unary_op.operand, parent_table_id);
+                if operand_type == TypeNode::VoidType {
+                    return if unary_op.operator == Operator::Not { TypeNode::BooleanType } else { TypeNode::NumberType };
+                }
                 match unary_op.operator {
                     Operator::Minus => {
                         if operand_type != TypeNode::NumberType {
-                            self.errors.push(format!("Unary minus expects a number type."));
+                            self.error(format!("Unary minus expects a number type."));
+                        }
+                        TypeNode::NumberType
+                    }
+                    Operator::Plus => {
+                        if operand_type != TypeNode::NumberType {
+                            self.error("Unary plus expects a number type.".to_string());
                         }
                         TypeNode::NumberType
                     }
+                    Operator::Not => {
+                        if operand_type != TypeNode::BooleanType {
+                            self.error(format!("Operator 'not' expects a boolean type."));
+                        }
+                        TypeNode::BooleanType
+                    }
                     _ => operand_type,
                 }
             }
+            ExpressionNode::Cast(cast) => {
+                let operand_type = self.analyze_expression(&mut cast.operand, parent_table_id);
+                if let TypeNode::ArrayType(..) = cast.target_type {
+                    self.error(format!("Cannot cast to array type {:?}.", cast.target_type));
+                }
+                if operand_type != TypeNode::NumberType && operand_type != TypeNode::BooleanType {
+                    self.error(format!(
+                        "Cannot cast {:?} to {:?}, only number and boolean values can be cast.",
+                        operand_type, cast.target_type
+                    ));
+                }
+                cast.target_type.clone()
+            }
+            ExpressionNode::ArrayLiteral(elements, _) => {
+                if elements.is_empty() {
+                    self.error("Cannot infer the type of an empty array literal.".to_string());
+                    return TypeNode::VoidType;
+                }
+
+                let element_types: Vec<TypeNode> = elements
+                    .iter_mut()
+                    .map(|element| self.analyze_expression(element, parent_table_id))
+                    .collect();
+                let element_type = element_types[0].clone();
+                if element_type == TypeNode::VoidType {
+                    self.error("Array elements cannot be void.".to_string());
+                }
+                if let Some(found) = element_types.iter().find(|t| **t != element_type) {
+                    self.error(format!(
+                        "Array literal elements must all have the same type: expected {:?}, found {:?}.",
+                        element_type, found
+                    ));
+                }
+
+                TypeNode::ArrayType(Box::new(element_type), elements.len())
+            }
+            ExpressionNode::Tuple(values, _) => {
+                let value_types: Vec<TypeNode> = values
+                    .iter_mut()
+                    .map(|value| self.analyze_value(value, parent_table_id))
+                    .collect();
+                TypeNode::TupleType(value_types)
+            }
             ExpressionNode::Literal(literal) => match &literal.value {
                 LiteralValue::NumberValue(_) => TypeNode::NumberType,
                 LiteralValue::BooleanValue(_) => TypeNode::BooleanType,
                 LiteralValue::StringValue(_) => TypeNode::StringType,
             },
-            ExpressionNode::Variable(var_name) => {
+            ExpressionNode::Variable(var_name, span) => {
                 let symbol_info = {
                     let symbol_table = self.symbol_tables.get(&parent_table_id).unwrap();
                     symbol_table.lookup(var_name, &self.symbol_tables)
                 };
 
                 if let Some(symbol_info) = symbol_info {
-                    if let SymbolType::Variable(var_type) = symbol_info.symbol_type.clone() {
-
+                    if let SymbolType::Variable(var_type) | SymbolType::Constant(var_type) | SymbolType::Parameter { param_type: var_type, .. } = symbol_info.symbol_type.clone() {
+                        // An exported procedure can be called from C without main ever running.
+                        if self.current_procedure_export && matches!(symbol_info.symbol_type, SymbolType::Constant(_)) && self.runtime_constants.contains(var_name.as_str()) {
+                            self.current_span = *span;
+                            self.error(format!(
+                                "Exported procedures cannot read the constant '{}', its value is only set when main starts.",
+                                var_name
+                            ));
+                        }
 
                         let mut current_table_id = Some(parent_table_id);
 
@@ -376,18 +873,20 @@ impl SemanticAnalyzer {
 
                         var_type
                     } else {
-                        self.errors.push(format!("'{}' is not a variable.", var_name));
+                        self.error(format!("'{}' is not a variable.", var_name));
                         TypeNode::VoidType
                     }
                 } else {
-                    self.errors.push(format!("Variable '{}' is not declared.", var_name));
+                    self.error(self.not_declared_message("Variable", var_name, parent_table_id));
                     TypeNode::VoidType
                 }
             }
             ExpressionNode::ProcedureCall(proc_call) => {
-                if proc_call.name == "print" {
-                    self.analyze_print_call(proc_call, parent_table_id);
-                    return TypeNode::VoidType;
+                if let Some(builtin) = builtins::find(&proc_call.name) {
+                    if let Some(analyze) = builtin.analyze {
+                        analyze(self, proc_call, parent_table_id);
+                        return builtin.return_type.clone();
+                    }
                 }
                 let (return_type, param_types) = {
                     let (return_type, param_types) = {
@@ -397,14 +896,14 @@ impl SemanticAnalyzer {
                         };
 
                         if let Some(symbol_info) = symbol_info {
-                            if let SymbolType::Procedure { return_type, param_types } = &symbol_info.symbol_type {
+                            if let SymbolType::Procedure { return_type, param_types, .. } = &symbol_info.symbol_type {
                                 (return_type.clone(), param_types.clone())
                             } else {
-                                self.errors.push(format!("'{}' is not a procedure.", proc_call.name));
+                                self.error(format!("'{}' is not a procedure.", proc_call.name));
                                 return TypeNode::VoidType;
                             }
                         } else {
-                            self.errors.push(format!("Procedure '{}' is not declared.", proc_call.name));
+                            self.error(self.not_declared_message("Procedure", &proc_call.name, parent_table_id));
                             return TypeNode::VoidType;
                         }
                     };
@@ -427,15 +926,15 @@ impl SemanticAnalyzer {
                 };
 
                 if proc_call.args.len() != param_types.len() {
-                    self.errors.push(format!(
+                    self.error(format!(
                         "Procedure '{}' expects {} arguments, but {} were provided.",
                         proc_call.name, param_types.len(), proc_call.args.len()
                     ));
                 } else {
                     for (arg, expected_type) in proc_call.args.iter_mut().zip(param_types) {
-                        let arg_type = self.analyze_expression(arg, parent_table_id);
-                        if &arg_type != &expected_type {
-                            self.errors.push(format!(
+                        let arg_type = self.analyze_value(arg, parent_table_id);
+                        if &arg_type != &expected_type && arg_type != TypeNode::VoidType {
+                            self.error(format!(
                                 "Type mismatch in procedure call: expected {:?}, found {:?}.",
                                 expected_type, arg_type
                             ));
@@ -449,6 +948,30 @@ impl SemanticAnalyzer {
         }
     }
 
+    // Reports an undeclared variable or procedure, suggesting the closest visible name of the same
+    // kind when it is at most two edits away, which catches most typos.
+    fn not_declared_message(&self, kind: &str, name: &str, table_id: usize) -> String {
+        let mut best: Option<(usize, &str)> = None;
+        let mut current_table_id = Some(table_id);
+        while let Some(table) = current_table_id.and_then(|id| self.symbol_tables.get(&id)) {
+            for (candidate, symbol) in &table.symbols {
+                let same_kind = match symbol.symbol_type {
+                    SymbolType::Procedure { .. } => kind == "Procedure",
+                    _ => kind == "Variable",
+                };
+                let distance = edit_distance(name, candidate);
+                if same_kind && distance <= 2 && best.map_or(true, |(d, b)| (distance, candidate.as_str()) < (d, b)) {
+                    best = Some((distance, candidate));
+                }
+            }
+            current_table_id = table.parent_id;
+        }
+
+        match best {
+            Some((_, suggestion)) => format!("{} '{}' is not declared, did you mean '{}'?", kind, name, suggestion),
+            None => format!("{} '{}' is not declared.", kind, name),
+        }
+    }
 
     fn traverse_and_check(&mut self, table_id: usize) {
         let mut stack = vec![table_id];
@@ -470,61 +993,164 @@ impl SemanticAnalyzer {
         }
     }
 
-    fn analyze_print_call(&mut self, proc_call: &mut ProcedureCallNode, parent_table_id: usize) {
-        if proc_call.args.is_empty() {
-            self.errors.push("Print statement requires at least one argument".into());
-            return;
+    // Translates a print placeholder (`{}` or `{:spec}`, passed without the braces) into the
+    // printf conversion for an argument of the given type.
+    //   {}    - default formatting for any printable type
+    //   {:d}  - a number without decimals
+    //   {:.N} - a number with N decimals
+    //   {:g}  - a number in compact or scientific notation, whichever is shorter
+    //   {:p}  - the address of a string
+    fn format_specifier(&self, spec: &str, arg_type: &TypeNode) -> Result<String, String> {
+        if *arg_type == TypeNode::VoidType {
+            return Err("Cannot print void type".into());
+        }
+        if let TypeNode::ArrayType(..) = arg_type {
+            return Err("Cannot print array type".into());
+        }
+        if let TypeNode::TupleType(..) = arg_type {
+            return Err("Cannot print tuple type".into());
         }
 
-        let mut format_string = String::new();
-        if let ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(ref string_literal) }) = proc_call.args[0] {
-            let mut expr_index = 0;
-
-            let mut chars = string_literal.chars().peekable();
+        let requires = |expected: TypeNode, specifier: String| {
+            if *arg_type == expected {
+                Ok(specifier)
+            } else {
+                Err(format!(
+                    "Format spec '{{{}}}' expects {:?}, found {:?}.",
+                    spec, expected, arg_type
+                ))
+            }
+        };
 
+        match spec {
+            "" => Ok(match arg_type {
+                TypeNode::NumberType => "%.2f",
+                TypeNode::BooleanType => "%d",
+                _ => "%s",
+            }.to_string()),
+            ":d" => requires(TypeNode::NumberType, "%.0f".to_string()),
+            ":g" => requires(TypeNode::NumberType, "%g".to_string()),
+            ":p" => requires(TypeNode::StringType, "%p".to_string()),
+            _ => match spec.strip_prefix(":.").map(str::parse::<u8>) {
+                Some(Ok(precision)) => requires(TypeNode::NumberType, format!("%.{}f", precision)),
+                _ => Err(format!("Unknown format spec '{{{}}}' in print statement.", spec)),
+            },
+        }
+    }
 
-            let mut args = proc_call.args[1..].to_vec();
-            while let Some(c) = chars.next() {
-                if c == '{' && chars.peek() == Some(&'}') {
-                    chars.next();
+    // Replaces every placeholder in `template` with the printf conversion for the matching
+    // argument, analyzing the arguments along the way. `context` names the call in errors.
+    fn translate_placeholders(&mut self, template: &str, args: &mut [ExpressionNode], parent_table_id: usize, context: &str) -> Option<String> {
+        let mut format_string = String::new();
+        let mut expr_index = 0;
+
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '{' && matches!(chars.peek(), Some('}') | Some(':')) {
+                let mut spec = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    spec.push(c);
+                }
+                if !closed {
+                    self.error(format!("Unterminated placeholder in {}!", context));
+                    return None;
+                }
 
-                    if expr_index < args.len() {
-                        let expr = &mut args[expr_index];
-                        let expr_type = self.analyze_expression(expr, parent_table_id);
-                        match expr_type {
-                            TypeNode::NumberType => format_string.push_str("%.2f"),
-                            TypeNode::BooleanType => format_string.push_str("%d"),
-                            TypeNode::StringType => format_string.push_str("%s"),
-                            TypeNode::VoidType => self.errors.push("Cannot print void type".into()),
-                        }
-                        expr_index += 1;
-                    } else {
-                        self.errors.push("Mismatched number of interpolation expressions and placeholders in print statement!".into());
-                        return;
+                if expr_index < args.len() {
+                    let expr = &mut args[expr_index];
+                    let expr_type = self.analyze_expression(expr, parent_table_id);
+                    match self.format_specifier(&spec, &expr_type) {
+                        Ok(specifier) => format_string.push_str(&specifier),
+                        Err(e) => self.error(e),
                     }
+                    expr_index += 1;
                 } else {
-                    format_string.push(c);
+                    self.error(format!("Mismatched number of interpolation expressions and placeholders in {}!", context));
+                    return None;
                 }
+            } else if c == '%' {
+                // The result is handed to printf, where a lone `%` would start a conversion.
+                format_string.push_str("%%");
+            } else {
+                format_string.push(c);
             }
-            format_string.push('\n');
+        }
+
+        if expr_index != args.len() {
+            self.error(format!("Mismatched number of interpolation expressions and placeholders in {}!", context));
+            return None;
+        }
+        Some(format_string)
+    }
+
+    pub(crate) fn analyze_print_call(&mut self, proc_call: &mut ProcedureCallNode, parent_table_id: usize) {
+        if proc_call.args.is_empty() {
+            self.error("Print statement requires at least one argument".into());
+            return;
+        }
 
-            if expr_index != args.len() {
-                self.errors.push("Mismatched number of interpolation expressions and placeholders in print statement!".into());
+        let mut format_string = String::new();
+        if let ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(ref string_literal), .. }) = proc_call.args[0] {
+            let template = string_literal.clone();
+            let Some(translated) = self.translate_placeholders(&template, &mut proc_call.args[1..], parent_table_id, "print statement") else {
                 return;
-            }
-            proc_call.args[0] = ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(format_string) });
+            };
+            format_string.push_str(&translated);
+            format_string.push('\n');
+            proc_call.args[0] = ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(format_string), span: proc_call.span });
         } else {
+            let mut specifiers = Vec::new();
             for expr in proc_call.args.iter_mut() {
                 match self.analyze_expression(expr, parent_table_id) {
-                    TypeNode::NumberType => format_string.push_str("%.2f "),
-                    TypeNode::BooleanType => format_string.push_str("%d "),
-                    TypeNode::StringType => format_string.push_str("%s "),
-                    TypeNode::VoidType => self.errors.push("Cannot print void type".into()),
+                    TypeNode::NumberType => specifiers.push("%.2f"),
+                    TypeNode::BooleanType => specifiers.push("%d"),
+                    TypeNode::StringType => specifiers.push("%s"),
+                    TypeNode::ArrayType(..) => self.error("Cannot print array type".into()),
+                    TypeNode::TupleType(..) => self.error("Cannot print tuple type".into()),
+                    TypeNode::VoidType => self.error("Cannot print void type".into()),
                 }
             }
+            // Arguments are separated by a single space, without one after the last.
+            format_string.push_str(&specifiers.join(" "));
             format_string.push('\n');
-            proc_call.args.insert(0, ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(format_string) }));
+            proc_call.args.insert(0, ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(format_string), span: proc_call.span }));
+        }
+
+    }
+
+    // `format` takes the same placeholders as print, but builds a string instead of printing it.
+    pub(crate) fn analyze_format_call(&mut self, proc_call: &mut ProcedureCallNode, parent_table_id: usize) {
+        let template = match proc_call.args.first() {
+            Some(ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(template), .. })) => template.clone(),
+            _ => {
+                self.error("Format requires a string literal as its first argument".into());
+                return;
+            }
+        };
+
+        if let Some(format_string) = self.translate_placeholders(&template, &mut proc_call.args[1..], parent_table_id, "format call") {
+            proc_call.args[0] = ExpressionNode::Literal(LiteralNode{ value: LiteralValue::StringValue(format_string), span: proc_call.span });
         }
+    }
+}
 
+// Levenshtein distance: the fewest single character insertions, deletions and substitutions
+// turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
     }
+    previous[b.len()]
 }
@@ -1,13 +1,22 @@
 use std::collections::HashMap;
+use std::fmt::Write;
 
-use super::node::TypeNode;
+use super::diagnostic::{Diagnostic, DiagnosticKind};
+use super::node::{Span, TypeNode};
 
 #[derive(Debug, Clone)]
 pub enum SymbolType {
     Variable(TypeNode),
+    // Declared at the top level, readable everywhere but never assigned to.
+    Constant(TypeNode),
+    Parameter {
+        param_type: TypeNode,
+        procedure: String,
+    },
     Procedure {
         return_type: TypeNode,
         param_types: Vec<TypeNode>,
+        noreturn: bool,
     },
 }
 
@@ -16,6 +25,7 @@ pub struct SymbolInfo {
     pub symbol_type: SymbolType,
     pub used: bool,
     pub initialized: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -54,13 +64,44 @@ impl SymbolTable {
         None
     }
 
-    pub fn check_unused_symbols(&self, warnings: &mut Vec<String>) {
+    // Renders this scope and all of its nested scopes, one level of indentation per scope,
+    // with symbols and child scopes sorted so the output is stable between runs.
+    pub fn render_tree(&self, symbol_tables: &HashMap<usize, SymbolTable>, depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        writeln!(output, "{}Scope {}", indent, self.id).unwrap();
+
+        let mut names: Vec<&String> = self.symbols.keys().collect();
+        names.sort();
+        for name in names {
+            let symbol = &self.symbols[name];
+            writeln!(output, "{}  {}: {:?} (used: {})", indent, name, symbol.symbol_type, symbol.used).unwrap();
+        }
+
+        let mut children = self.children.clone();
+        children.sort();
+        for child_id in children {
+            if let Some(child) = symbol_tables.get(&child_id) {
+                child.render_tree(symbol_tables, depth + 1, output);
+            }
+        }
+    }
+
+    pub fn check_unused_symbols(&self, warnings: &mut Vec<Diagnostic>) {
         for (name, symbol) in &self.symbols {
             if !symbol.used {
-                if let SymbolType::Variable(_) = symbol.symbol_type {
-                    warnings.push(format!("Variable '{}' is declared but never used.", name));
-                } else {
-                    warnings.push(format!("Procedure '{}' is declared but never used.", name));
+                match &symbol.symbol_type {
+                    SymbolType::Variable(_) => {
+                        warnings.push(Diagnostic { message: format!("Variable '{}' is declared but never used.", name), span: symbol.span, kind: DiagnosticKind::Unused });
+                    }
+                    SymbolType::Constant(_) => {
+                        warnings.push(Diagnostic { message: format!("Constant '{}' is declared but never used.", name), span: symbol.span, kind: DiagnosticKind::Unused });
+                    }
+                    SymbolType::Parameter { procedure, .. } => {
+                        warnings.push(Diagnostic { message: format!("Parameter '{}' in procedure '{}' is never used.", name, procedure), span: symbol.span, kind: DiagnosticKind::Unused });
+                    }
+                    SymbolType::Procedure { .. } => {
+                        warnings.push(Diagnostic { message: format!("Procedure '{}' is declared but never used.", name), span: symbol.span, kind: DiagnosticKind::Unused });
+                    }
                 }
             }
         }